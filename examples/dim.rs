@@ -0,0 +1,31 @@
+//! Dims the preferred backlight of each panel by 10%, never below 5%, the
+//! way a panel applet would handle a scroll on its icon.
+//!
+//! ```text
+//! cargo run --example dim [DEVICE_GLOB]
+//! ```
+
+extern crate backctl;
+
+use std::env;
+
+use backctl::{select, Backlights, Class, Curve, CurvePolicy, Level, Limit, Selector, Source, Update};
+
+fn main() -> backctl::Result<()> {
+    let selector = Selector {
+        class: Some(Class::Backlight),
+        devices: env::args().skip(1).collect(),
+        ..Selector::default()
+    };
+    let backlights = select::primary(selector.select(Backlights::new(&Source::Udev, &[Class::Backlight])?)?);
+
+    let update = Update::dec("10%")?
+        .with_curve(CurvePolicy::Fixed(Curve::Gamma(2.2)))
+        .with_floor(Limit::new(Level::Percent(5.0)));
+    for backlight in backlights {
+        let name = backlight.sysname.clone();
+        let backlight = update.apply(backlight)?;
+        println!("{} is now at {}", name, backlight.get_brightness()?);
+    }
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! Prints every backlight with its brightness along the perceptual curve
+//! `backctl get` uses.
+//!
+//! ```text
+//! cargo run --example list [SYSFS_ROOT]
+//! ```
+
+extern crate backctl;
+
+use std::env;
+use std::path::PathBuf;
+
+use backctl::{Backlights, Class, Curve, CurvePolicy, Source};
+
+fn main() -> backctl::Result<()> {
+    let source = match env::args_os().nth(1) {
+        Some(root) => Source::Sysfs(PathBuf::from(root)),
+        None => Source::Udev,
+    };
+    let curve = CurvePolicy::Auto { perceptual: Curve::Gamma(2.2), overrides: Vec::new() };
+
+    for backlight in Backlights::new(&source, Class::ALL)? {
+        let brightness = backlight.get_brightness()?;
+        let max = backlight.get_max_brightness()?;
+        let percent = curve.resolve(&backlight).to_percent(brightness, max);
+        println!("{:<30} {:>6}/{:<6} {:>4.0}%", backlight.sysname, brightness, max, percent);
+    }
+    Ok(())
+}
@@ -0,0 +1,372 @@
+//! Automatic brightness from an ambient light sensor.
+//!
+//! Sensors are IIO devices with an `in_illuminance_raw` channel, and
+//! optionally `in_illuminance_scale` and `in_illuminance_offset`, which give
+//! the illuminance in lux as `(raw + offset) * scale`.
+//!
+//! The curve from lux to brightness starts out as the one configured and is
+//! then bent towards the brightness the user picks by hand at each light
+//! level, so automatic changes stop undoing manual ones.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use {Error, ErrorKind, Result};
+
+/// Where each lux level puts the backlight unless told otherwise: dim in the
+/// dark, full in daylight
+pub const DEFAULT_CURVE: &str = "0:5,10:20,100:40,1000:70,10000:100";
+
+/// How close two light levels must be, in log lux, to count as the same
+/// when learning (a factor of about 1.6)
+const NEIGHBOURHOOD: f64 = 0.5;
+
+/// How many manual choices are remembered
+const MAX_SAMPLES: usize = 64;
+
+/// Where `lux` falls on the scale the eye works in
+fn position(lux: f64) -> f64 {
+    (lux.max(0.0) + 1.0).ln()
+}
+
+/// An IIO device measuring illuminance
+#[derive(Clone, Debug)]
+pub struct Sensor {
+    pub path: PathBuf,
+}
+
+impl Sensor {
+    /// Uses the IIO device directory `path`
+    pub fn new(path: PathBuf) -> Self {
+        Sensor { path }
+    }
+
+    /// The first IIO device under `sysfs` (normally `/sys`) with an
+    /// illuminance channel
+    pub fn find(sysfs: &Path) -> Result<Self> {
+        let dir = sysfs.join("bus/iio/devices");
+        let mut devices = Vec::new();
+        match fs::read_dir(&dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    devices.push(entry?.path());
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        devices.sort();
+        match devices.into_iter().find(|d| d.join("in_illuminance_raw").exists()) {
+            Some(path) => Ok(Sensor::new(path)),
+            None => bail!("No ambient light sensor found in {}", dir.display()),
+        }
+    }
+
+    /// The current illuminance in lux
+    pub fn read_lux(&self) -> Result<f64> {
+        let raw = match self.read("in_illuminance_raw")? {
+            Some(raw) => raw,
+            None => bail!("{} has no in_illuminance_raw", self.path.display()),
+        };
+        let offset = self.read("in_illuminance_offset")?.unwrap_or(0.0);
+        let scale = self.read("in_illuminance_scale")?.unwrap_or(1.0);
+        Ok(((raw + offset) * scale).max(0.0))
+    }
+
+    /// An attribute's value, or `None` if the driver doesn't provide it
+    fn read(&self, attribute: &str) -> Result<Option<f64>> {
+        let path = self.path.join(attribute);
+        match fs::read_to_string(&path) {
+            Ok(value) => match value.trim().parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => bail!("Invalid {} '{}'", path.display(), value.trim()),
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Maps illuminance to a brightness percentage through a series of points.
+/// Between points the percentage is interpolated along the logarithm of the
+/// illuminance, since the eye adapts to ratios of light rather than
+/// differences.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LuxCurve {
+    points: Vec<(f64, f64)>,
+}
+
+impl LuxCurve {
+    /// Parses points written `LUX:PERCENT`, separated by commas, such as
+    /// `0:5,100:40,10000:100`
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid = || ErrorKind::InvalidValue(
+            format!("Invalid light curve '{}', expected LUX:PERCENT points such as {}", s, DEFAULT_CURVE));
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        for point in s.split(',') {
+            let mut parts = point.splitn(2, ':');
+            let lux: f64 = parts.next().and_then(|l| l.trim().parse().ok()).ok_or_else(invalid)?;
+            let percent: f64 = parts.next().and_then(|p| p.trim().trim_end_matches('%').parse().ok())
+                .ok_or_else(invalid)?;
+            if !(lux >= 0.0 && (0.0..=100.0).contains(&percent)) {
+                bail!(invalid());
+            }
+            if points.last().is_some_and(|&(last, _)| lux <= last) {
+                bail!(ErrorKind::InvalidValue(format!("Light curve '{}' must list lux in increasing order", s)));
+            }
+            points.push((lux, percent));
+        }
+        Ok(LuxCurve { points })
+    }
+
+    /// The brightness percentage for `lux`
+    pub fn percent(&self, lux: f64) -> f64 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if lux <= first.0 {
+            return first.1;
+        }
+        if lux >= last.0 {
+            return last.1;
+        }
+        let i = self.points.iter().position(|&(l, _)| l > lux).unwrap();
+        let ((lux0, percent0), (lux1, percent1)) = (self.points[i - 1], self.points[i]);
+        let t = (position(lux) - position(lux0)) / (position(lux1) - position(lux0));
+        percent0 + (percent1 - percent0) * t
+    }
+}
+
+impl Default for LuxCurve {
+    fn default() -> Self {
+        LuxCurve::parse(DEFAULT_CURVE).unwrap()
+    }
+}
+
+/// A light curve bent towards the brightness picked by hand at each light
+/// level.
+///
+/// Each choice is kept as a point on the curve. A new choice replaces older
+/// ones at about the same light level and any that contradict it (a
+/// brighter choice in dimmer light, or the reverse), so the newest always
+/// holds and the points stay monotone. Points of the configured curve fill
+/// the gaps wherever they agree with the choices.
+#[derive(Clone, Debug)]
+pub struct Learned {
+    base: LuxCurve,
+    /// `(lux, percent)` choices, oldest first
+    samples: Vec<(f64, f64)>,
+    curve: LuxCurve,
+}
+
+impl Learned {
+    /// `base` with no choices learned yet
+    pub fn new(base: LuxCurve) -> Self {
+        Learned { curve: base.clone(), base, samples: Vec::new() }
+    }
+
+    /// `base` with the choices saved in `path`, if it exists
+    pub fn load(base: LuxCurve, path: &Path) -> Result<Self> {
+        let mut learned = Learned::new(base);
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(learned),
+            Err(e) => return Err(e.into()),
+        };
+        for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+            let mut fields = line.split_whitespace();
+            match (fields.next().map(str::parse), fields.next().map(str::parse)) {
+                (Some(Ok(lux)), Some(Ok(percent))) => learned.record(lux, percent),
+                _ => bail!("Invalid learned brightness '{}' in {}", line, path.display()),
+            }
+        }
+        Ok(learned)
+    }
+
+    /// Writes the choices to `path`, one `LUX PERCENT` line each
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let lines: String = self.samples.iter().map(|&(lux, percent)| format!("{} {}\n", lux, percent)).collect();
+        fs::write(path, lines)?;
+        Ok(())
+    }
+
+    /// Learns that `percent` was wanted at `lux`
+    pub fn record(&mut self, lux: f64, percent: f64) {
+        let (lux, percent) = (lux.max(0.0), percent.clamp(0.0, 100.0));
+        self.samples.retain(|&(l, p)| {
+            (position(l) - position(lux)).abs() >= NEIGHBOURHOOD &&
+                !(l < lux && p > percent) && !(l > lux && p < percent)
+        });
+        self.samples.push((lux, percent));
+        if self.samples.len() > MAX_SAMPLES {
+            self.samples.remove(0);
+        }
+        self.curve = self.fit();
+    }
+
+    fn fit(&self) -> LuxCurve {
+        let mut points = self.samples.clone();
+        for &(lux, percent) in &self.base.points {
+            let near = self.samples.iter().any(|&(l, _)| (position(l) - position(lux)).abs() < NEIGHBOURHOOD);
+            let agrees = self.samples.iter().all(|&(l, p)| if l < lux { p <= percent } else { p >= percent });
+            if !near && agrees {
+                points.push((lux, percent));
+            }
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        LuxCurve { points }
+    }
+
+    /// The curve to follow
+    pub fn curve(&self) -> &LuxCurve {
+        &self.curve
+    }
+}
+
+/// Smooths sensor readings and decides when the brightness should follow
+/// them, so a passing shadow or a flickering light doesn't move it
+#[derive(Clone, Debug)]
+pub struct Smoother {
+    /// How much each new sample counts against the running average, from
+    /// just above 0 (barely) to 1 (no smoothing)
+    pub smoothing: f64,
+    /// How many percentage points the brightness must want to move by
+    /// before it does
+    pub hysteresis: f64,
+    lux: Option<f64>,
+    applied: Option<f64>,
+}
+
+impl Smoother {
+    pub fn new(smoothing: f64, hysteresis: f64) -> Self {
+        Smoother { smoothing, hysteresis, lux: None, applied: None }
+    }
+
+    /// The smoothed illuminance, once there has been a sample
+    pub fn lux(&self) -> Option<f64> {
+        self.lux
+    }
+
+    /// Treats `percent` as where the brightness now is, so it's only moved
+    /// again once the light changes
+    pub fn settle(&mut self, percent: f64) {
+        self.applied = Some(percent);
+    }
+
+    /// Takes a sample, returning the percentage to move to if the smoothed
+    /// level has moved far enough from the last one returned
+    pub fn sample(&mut self, lux: f64, curve: &LuxCurve) -> Option<f64> {
+        let lux = match self.lux {
+            Some(average) => average + self.smoothing * (lux - average),
+            None => lux,
+        };
+        self.lux = Some(lux);
+        let percent = curve.percent(lux);
+        match self.applied {
+            Some(applied) if (percent - applied).abs() < self.hysteresis => None,
+            _ => {
+                self.applied = Some(percent);
+                Some(percent)
+            }
+        }
+    }
+}
+
+/// Checks the weight `daemon --auto` gives each sensor sample
+pub fn smoothing(weight: f64) -> Result<f64> {
+    if !(weight > 0.0 && weight <= 1.0) {
+        bail!(ErrorKind::InvalidValue(format!("Smoothing must be above 0 and at most 1, got {}", weight)));
+    }
+    Ok(weight)
+}
+
+/// Checks the change in percentage points `daemon --auto` ignores
+pub fn hysteresis(points: f64) -> Result<f64> {
+    if !(points.is_finite() && points >= 0.0) {
+        bail!(ErrorKind::InvalidValue(format!("Hysteresis must be a positive number of percentage points, got {}", points)));
+    }
+    Ok(points)
+}
+
+/// Brightness that follows an ambient light sensor
+pub struct Auto {
+    pub sensor: Sensor,
+    pub curve: Learned,
+    pub smoother: Smoother,
+    /// How often the sensor is sampled
+    pub interval: Duration,
+    /// Where what's learned from manual changes is kept between runs
+    pub store: Option<PathBuf>,
+    /// How many changes have been made by hand, so a fade started before
+    /// one knows to stop
+    changes: u64,
+}
+
+impl Auto {
+    pub fn new(sensor: Sensor, curve: Learned, smoother: Smoother, interval: Duration,
+               store: Option<PathBuf>) -> Self {
+        Auto { sensor, curve, smoother, interval, store, changes: 0 }
+    }
+
+    /// Takes the lock for a change made by hand, which stops any fade `run`
+    /// still has under way
+    pub fn manual<'a>(auto: &'a Mutex<Auto>) -> MutexGuard<'a, Auto> {
+        let mut guard = auto.lock().unwrap();
+        guard.changes += 1;
+        guard
+    }
+
+    /// Reads the sensor, returning the percentage to move to if the smoothed
+    /// level calls for it
+    pub fn sample(&mut self) -> Result<Option<f64>> {
+        let lux = self.sensor.read_lux()?;
+        Ok(self.smoother.sample(lux, self.curve.curve()))
+    }
+
+    /// Learns from the brightness having been set to `percent` by hand at
+    /// the current light level, saving it to `store`
+    pub fn adjusted(&mut self, percent: f64) -> Result<()> {
+        let lux = match self.smoother.lux() {
+            Some(lux) => lux,
+            None => return Ok(()),
+        };
+        self.curve.record(lux, percent);
+        self.smoother.settle(percent);
+        match self.store {
+            Some(ref path) => self.curve.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Samples the sensor every `interval`, calling `apply` with the
+    /// percentage to move to whenever the smoothed level calls for it. The
+    /// lock is only held while sampling, so changes made by hand never wait
+    /// for `apply`; its second argument tells it when one has been made
+    /// since the sample, and it should stop. A failed sample is handed to
+    /// `failed` and skipped, so this never returns.
+    pub fn run<F, E>(auto: &Mutex<Auto>, mut apply: F, mut failed: E) -> !
+        where F: FnMut(f64, &dyn Fn() -> bool),
+              E: FnMut(Error)
+    {
+        loop {
+            let (percent, changes, interval) = {
+                let mut auto = auto.lock().unwrap();
+                let percent = auto.sample().unwrap_or_else(|e| {
+                    failed(e);
+                    None
+                });
+                (percent, auto.changes, auto.interval)
+            };
+            if let Some(percent) = percent {
+                // A change made by hand holds the lock while it's under way
+                apply(percent, &|| auto.try_lock().map_or(true, |auto| auto.changes != changes));
+            }
+            thread::sleep(interval);
+        }
+    }
+}
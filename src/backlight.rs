@@ -0,0 +1,377 @@
+//! Backlight devices and the sources they are discovered from
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::vec;
+
+use libc;
+use udev;
+
+use curve::Scale;
+use logind;
+use {Error, ErrorKind, Result, ResultExt};
+
+/// Where backlight devices are discovered
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// Enumerate the `backlight` subsystem through libudev
+    Udev,
+    /// Walk a directory laid out like `/sys`, reading `class/<subsystem>/*`
+    Sysfs(PathBuf),
+}
+
+/// The device classes that share the `brightness`/`max_brightness` layout
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Class {
+    /// Screen backlights
+    #[default]
+    Backlight,
+    /// LEDs, such as keyboard backlights (`*::kbd_backlight`) and indicators
+    Leds,
+}
+
+impl Class {
+    /// Every class, in the order devices are listed
+    pub const ALL: &'static [Class] = &[Class::Backlight, Class::Leds];
+
+    /// Parses a class as given to `--class`
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "backlight" => Ok(Class::Backlight),
+            "leds" => Ok(Class::Leds),
+            _ => bail!(ErrorKind::InvalidValue(format!("Unknown device class '{}'", name))),
+        }
+    }
+
+    /// The udev subsystem, which is also the directory under `/sys/class`
+    pub fn subsystem(self) -> &'static str {
+        match self {
+            Class::Backlight => "backlight",
+            Class::Leds => "leds",
+        }
+    }
+}
+
+/// How brightness is written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// Write the `brightness` attribute directly
+    Sysfs,
+    /// Ask systemd-logind to write it on behalf of the caller's session
+    Logind,
+    /// Write sysfs, falling back to logind when permission is denied
+    Auto,
+}
+
+/// A DRM connector, the output a panel is plugged into
+#[derive(Clone, Debug, PartialEq)]
+pub struct Connector {
+    /// The GPU, e.g. `card0`
+    pub card: String,
+    /// The connector name, e.g. `eDP-1`
+    pub name: String,
+}
+
+impl Connector {
+    /// Parses a connector's sysname, such as `card0-eDP-1`
+    pub fn parse(sysname: &str) -> Option<Self> {
+        let rest = sysname.strip_prefix("card")?;
+        let dash = rest.find('-')?;
+        if dash == 0 || !rest[..dash].bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(Connector { card: sysname[..dash + 4].to_string(), name: rest[dash + 1..].to_string() })
+    }
+
+    /// Whether this is a built-in panel rather than an external monitor
+    pub fn is_internal(&self) -> bool {
+        ["eDP", "LVDS", "DSI"].iter().any(|kind| self.name.starts_with(kind))
+    }
+}
+
+impl fmt::Display for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// A `backlight` or `leds` class device and how to write it
+#[derive(Clone)]
+pub struct Backlight {
+    /// The device's directory, holding `brightness` and `max_brightness`
+    pub root: PathBuf,
+    /// The name under its class, such as `intel_backlight`
+    pub sysname: String,
+    /// udev properties such as `ID_PATH`, or what `uevent` holds for a
+    /// `Source::Sysfs` tree
+    pub properties: HashMap<String, String>,
+    /// The driver of the nearest parent that has one
+    pub driver: Option<String>,
+    /// The DRM connector the panel is plugged into, if it can be told
+    pub connector: Option<Connector>,
+    /// How brightness is written
+    pub backend: Backend,
+}
+
+impl Backlight {
+    /// Builds a backlight from a udev device in the `backlight` or `leds`
+    /// subsystem
+    pub fn from_device(dev: &udev::Device) -> Self {
+        let properties = dev.properties()
+            .map(|p| (p.name().to_string_lossy().into_owned(), p.value().to_string_lossy().into_owned()))
+            .collect();
+        // The backlight itself has no driver, so walk up until we find the one
+        // that owns it (i915, amdgpu, acpi-video, ...)
+        let mut driver = None;
+        let mut parent = dev.parent();
+        while let Some(p) = parent {
+            if let Some(d) = p.driver() {
+                driver = Some(d.to_string_lossy().into_owned());
+                break;
+            }
+            parent = p.parent();
+        }
+        let connector = dev.parent_with_subsystem_devtype(Path::new("drm"), Path::new("drm_connector"))
+            .ok()
+            .and_then(|p| p)
+            .and_then(|p| Connector::parse(&p.sysname().to_string_lossy()));
+        Backlight {
+            root: PathBuf::from(dev.syspath()),
+            sysname: dev.sysname().to_string_lossy().into_owned(),
+            properties,
+            driver,
+            connector,
+            backend: Backend::Auto,
+        }
+    }
+
+    /// Builds a backlight from a sysfs-style device directory without udev.
+    /// Properties come from the kernel's `uevent` file, and the driver and
+    /// connector from the nearest `driver` link and `cardN-*` directory
+    /// above the device.
+    pub fn from_dir(path: &Path) -> Result<Self> {
+        let root = fs::canonicalize(path)?;
+        let sysname = path.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let mut properties = HashMap::new();
+        // Devices are found through their class directory, which names the
+        // subsystem even when there is no uevent file to say so
+        if let Some(class) = path.parent().and_then(Path::file_name) {
+            properties.insert("SUBSYSTEM".to_string(), class.to_string_lossy().into_owned());
+        }
+        if let Ok(uevent) = fs::read_to_string(root.join("uevent")) {
+            for line in uevent.lines() {
+                if let Some(i) = line.find('=') {
+                    properties.insert(line[..i].to_string(), line[i + 1..].to_string());
+                }
+            }
+        }
+
+        let driver = root.ancestors().skip(1)
+            .filter_map(|dir| fs::read_link(dir.join("driver")).ok())
+            .filter_map(|link| link.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .next();
+
+        let connector = root.ancestors().skip(1)
+            .filter_map(|dir| dir.file_name())
+            .filter_map(|name| Connector::parse(&name.to_string_lossy()))
+            .next();
+
+        Ok(Backlight { root, sysname, properties, driver, connector, backend: Backend::Auto })
+    }
+
+    /// The kernel subsystem, which is how logind and saved state tell
+    /// device classes apart
+    pub fn subsystem(&self) -> &str {
+        self.properties.get("SUBSYSTEM").map_or("backlight", String::as_str)
+    }
+
+    /// Turns a failed attribute access into the matching error kind.
+    /// Attributes a driver doesn't have stay plain I/O errors, so callers can
+    /// still tell them apart by `NotFound`.
+    fn attribute_error(&self, attribute: &str, e: io::Error) -> Error {
+        let path = self.root.join(attribute);
+        match e.raw_os_error() {
+            Some(libc::EACCES) | Some(libc::EPERM) => ErrorKind::PermissionDenied(path).into(),
+            Some(libc::ENODEV) | Some(libc::ENXIO) => ErrorKind::DeviceVanished(path).into(),
+            Some(libc::ENOENT) if !self.root.exists() => ErrorKind::DeviceVanished(path).into(),
+            _ => e.into(),
+        }
+    }
+
+    fn read_attribute(&self, attribute: &str) -> Result<String> {
+        let mut buf = String::new();
+        fs::File::open(self.root.join(attribute))
+            .and_then(|mut f| f.read_to_string(&mut buf))
+            .map_err(|e| self.attribute_error(attribute, e))?;
+        Ok(buf.trim().to_string())
+    }
+
+    fn read_value(&self, attribute: &str) -> Result<u32> {
+        Ok(self.read_attribute(attribute)?.parse()?)
+    }
+
+    /// Reads `max_brightness`, the highest value `set_brightness` accepts
+    pub fn get_max_brightness(&self) -> Result<u32> {
+        self.read_value("max_brightness")
+    }
+
+    /// Reads `brightness`, the value last written
+    pub fn get_brightness(&self) -> Result<u32> {
+        self.read_value("brightness")
+    }
+
+    /// Reads `actual_brightness`, what the hardware reports it is showing
+    pub fn get_actual_brightness(&self) -> Result<u32> {
+        self.read_value("actual_brightness")
+    }
+
+    /// Reads the backlight `type` attribute (firmware, platform or raw)
+    pub fn get_type(&self) -> Result<String> {
+        self.read_attribute("type")
+    }
+
+    /// Whether the device hangs straight off a PCI display controller, as
+    /// the raw backlights of amdgpu, nouveau and radeon do
+    pub fn on_pci_display(&self) -> bool {
+        let parent = match self.root.parent().and_then(Path::parent) {
+            Some(parent) => parent,
+            None => return false,
+        };
+        let pci = fs::read_link(parent.join("subsystem")).ok()
+            .is_some_and(|link| link.file_name().is_some_and(|name| name == "pci"));
+        // PCI base class 0x03 is for display controllers
+        pci && fs::read_to_string(parent.join("class")).is_ok_and(|class| class.trim().starts_with("0x03"))
+    }
+
+    /// Reads the `scale` attribute, treating kernels without it as unknown
+    pub fn get_scale(&self) -> Scale {
+        fs::read_to_string(self.root.join("scale")).ok()
+            .and_then(|s| Scale::parse(s.trim()).ok())
+            .unwrap_or(Scale::Unknown)
+    }
+
+    /// Reads `bl_power`, or `None` for drivers that don't have it
+    pub fn get_power(&self) -> Result<Option<u32>> {
+        if !self.root.join("bl_power").exists() {
+            return Ok(None);
+        }
+        self.read_value("bl_power").map(Some)
+    }
+
+    /// Writes `bl_power`. logind can only set brightness, so this always
+    /// goes through sysfs.
+    pub fn set_power(&self, state: u32) -> Result<()> {
+        self.write_attribute("bl_power", state)
+            .map_err(|e| self.write_error("bl_power", state, e))
+    }
+
+    /// Writes `brightness` through this backlight's `backend`
+    pub fn set_brightness(&self, brightness: u32) -> Result<()> {
+        match self.backend {
+            Backend::Sysfs => self.write_attribute("brightness", brightness)
+                .map_err(|e| self.write_error("brightness", brightness, e)),
+            Backend::Logind => logind::set_brightness(self.subsystem(), &self.sysname, brightness),
+            Backend::Auto => match self.write_attribute("brightness", brightness) {
+                Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    logind::set_brightness(self.subsystem(), &self.sysname, brightness)
+                        .chain_err(|| ErrorKind::PermissionDenied(self.root.join("brightness")))
+                }
+                result => result.map_err(|e| self.write_error("brightness", brightness, e)),
+            },
+        }
+    }
+
+    /// Like `attribute_error`, but a driver refusing the value itself is
+    /// reported as such
+    fn write_error(&self, attribute: &str, value: u32, e: io::Error) -> Error {
+        match e.raw_os_error() {
+            Some(libc::EINVAL) | Some(libc::ERANGE) => {
+                ErrorKind::WriteRejected(self.root.join(attribute), value).into()
+            }
+            _ => self.attribute_error(attribute, e),
+        }
+    }
+
+    fn write_attribute(&self, attribute: &str, value: u32) -> io::Result<()> {
+        // sysfs ignores the truncate, but plain files standing in for it don't
+        let mut f = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(self.root.as_path().join(attribute))?;
+        f.write_all(&value.to_string().into_bytes())
+    }
+}
+
+/// The devices found in a `Source`, sorted within each class
+pub struct Backlights {
+    iter: vec::IntoIter<Backlight>,
+}
+
+impl Backlights {
+    /// Enumerates every device in `classes`, one class after another
+    pub fn new(source: &Source, classes: &[Class]) -> Result<Self> {
+        let mut devs = Vec::new();
+        for &class in classes {
+            devs.extend(match *source {
+                Source::Udev => Backlights::scan_udev(class)?,
+                Source::Sysfs(ref root) => Backlights::scan_dir(&root.join("class").join(class.subsystem()))?,
+            });
+        }
+        Backlights::adopt_internal_connector(&mut devs);
+        Ok(Backlights { iter: devs.into_iter() })
+    }
+
+    /// Firmware and platform interfaces sit outside the DRM tree but drive
+    /// the built-in panel, so they take its connector when there's only one
+    fn adopt_internal_connector(devs: &mut [Backlight]) {
+        let mut internal: Vec<Connector> = devs.iter()
+            .filter_map(|bl| bl.connector.clone())
+            .filter(Connector::is_internal)
+            .collect();
+        internal.dedup();
+        if internal.len() != 1 {
+            return;
+        }
+        for bl in devs.iter_mut().filter(|bl| bl.connector.is_none()) {
+            if let Ok("firmware") | Ok("platform") = bl.get_type().as_ref().map(String::as_str) {
+                bl.connector = Some(internal[0].clone());
+            }
+        }
+    }
+
+    fn scan_udev(class: Class) -> Result<Vec<Backlight>> {
+        let context = udev::Context::new()?;
+        let mut enumerator = udev::Enumerator::new(&context)?;
+        enumerator.match_is_initialized()?;
+        enumerator.match_subsystem(class.subsystem())?;
+        let mut devs: Vec<Backlight> = enumerator.scan_devices()?.map(|dev| Backlight::from_device(&dev)).collect();
+        devs.sort_by(|a, b| a.sysname.cmp(&b.sysname));
+        Ok(devs)
+    }
+
+    fn scan_dir(class: &Path) -> Result<Vec<Backlight>> {
+        let mut paths = Vec::new();
+        if !class.is_dir() {
+            return Ok(Vec::new());
+        }
+        for entry in fs::read_dir(class)? {
+            paths.push(entry?.path());
+        }
+        paths.sort();
+        paths.iter().map(|p| Backlight::from_dir(p)).collect()
+    }
+}
+
+impl Iterator for Backlights {
+    type Item = Backlight;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
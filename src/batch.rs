@@ -0,0 +1,152 @@
+//! Writing several devices in one go, where one device failing doesn't stop
+//! the others
+
+use std::io::Write;
+
+use backlight::Backlight;
+use curve::CurvePolicy;
+use fade::Fade;
+use output::{self, Format, Report};
+use update::Update;
+use {exit_code, ErrorKind, Result};
+
+/// What a batch left behind
+pub struct Outcome {
+    /// The devices written, each with the brightness it had before. Empty
+    /// when a strict batch failed, even if some couldn't be put back.
+    pub written: Vec<(Backlight, Option<u32>)>,
+    /// A single device's error, or `DevicesFailed` naming every failure
+    /// when there was more than one device
+    pub result: Result<()>,
+}
+
+impl Outcome {
+    /// Reports the devices written in a machine-readable `format` (plain
+    /// text stays silent, as it always has) and hands back the result
+    pub fn report(self, curve: &CurvePolicy, format: Format, out: &mut dyn Write) -> Result<()> {
+        if format != Format::Text {
+            let mut reports = Vec::new();
+            for (bl, old) in &self.written {
+                reports.push(Report::written(bl, *old, curve)?);
+            }
+            output::write(format, &reports, out)?;
+        }
+        self.result
+    }
+}
+
+/// Runs `write` on each device. With `strict`, if any fails, those that
+/// succeeded are put back with `undo`, which is given the brightness each
+/// had before; `put_back` suits writes that only change brightness.
+pub fn write_each<W, U>(backlights: Vec<Backlight>, strict: bool, mut write: W, undo: U) -> Outcome
+    where W: FnMut(&Backlight) -> Result<()>,
+          U: FnMut(&Backlight, Option<u32>) -> Result<()>
+{
+    let previous = previous(&backlights);
+    let results = backlights.iter().map(&mut write).collect();
+    finish(backlights, previous, results, strict, undo)
+}
+
+/// Applies each device's update, fading them all together when `fade` is
+/// given, and with `strict` puts every device back if any fails
+pub fn apply(updates: Vec<(Backlight, Update)>, fade: Option<&Fade>, strict: bool) -> Outcome {
+    let backlights: Vec<Backlight> = updates.iter().map(|(bl, _)| bl.clone()).collect();
+    let previous = previous(&backlights);
+    let results = match fade {
+        Some(fade) => {
+            let mut results: Vec<Result<()>> = Vec::new();
+            let mut targets = Vec::new();
+            for (bl, update) in &updates {
+                match update.target(bl) {
+                    Ok(target) => {
+                        targets.push((bl.clone(), target, update));
+                        results.push(Ok(()));
+                    }
+                    Err(e) => results.push(Err(e)),
+                }
+            }
+            let frames: Vec<(Backlight, u32)> = targets.iter().map(|(bl, target, _)| (bl.clone(), *target)).collect();
+            let mut faded = fade.run(&frames).into_iter().zip(&targets);
+            for result in results.iter_mut().filter(|r| r.is_ok()) {
+                let (outcome, (bl, target, update)) = faded.next().unwrap();
+                *result = outcome.and_then(|()| update.verify(bl, *target));
+            }
+            results
+        }
+        None => updates.into_iter().map(|(bl, update)| update.apply(bl).map(|_| ())).collect(),
+    };
+    finish(backlights, previous, results, strict, put_back)
+}
+
+/// Puts a device back to the brightness it had before a batch wrote it
+pub fn put_back(backlight: &Backlight, previous: Option<u32>) -> Result<()> {
+    match previous {
+        Some(previous) => backlight.set_brightness(previous),
+        None => bail!("The brightness of {} before the change is unknown", backlight.sysname),
+    }
+}
+
+/// The brightness of each device, taken before anything is written for
+/// reports and `strict`
+fn previous(backlights: &[Backlight]) -> Vec<Option<u32>> {
+    backlights.iter().map(|bl| bl.get_brightness().ok()).collect()
+}
+
+/// Turns how each device fared into a single result, undoing the
+/// successful writes under `strict` if anything failed
+fn finish<U>(backlights: Vec<Backlight>, previous: Vec<Option<u32>>, results: Vec<Result<()>>, strict: bool,
+             mut undo: U) -> Outcome
+    where U: FnMut(&Backlight, Option<u32>) -> Result<()>
+{
+    let mut failed = Vec::new();
+    let mut succeeded = Vec::new();
+    for (i, result) in results.into_iter().enumerate() {
+        match result {
+            Ok(()) => succeeded.push(i),
+            Err(e) => failed.push((i, e)),
+        }
+    }
+    let written = |indices: &[usize]| -> Vec<(Backlight, Option<u32>)> {
+        indices.iter().map(|&i| (backlights[i].clone(), previous[i])).collect()
+    };
+    if failed.is_empty() {
+        return Outcome { written: written(&succeeded), result: Ok(()) };
+    }
+    if backlights.len() == 1 {
+        return Outcome { written: Vec::new(), result: Err(failed.remove(0).1) };
+    }
+
+    let mut summary: Vec<String> = failed.iter()
+        .map(|(i, e)| format!("{}: {}", backlights[*i].sysname, e))
+        .collect();
+    let names = |indices: &[usize]| indices.iter()
+        .map(|&i| backlights[i].sysname.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut left = succeeded.clone();
+    if strict {
+        let (mut restored, mut stuck) = (Vec::new(), Vec::new());
+        for &i in &succeeded {
+            match undo(&backlights[i], previous[i]) {
+                Ok(()) => restored.push(i),
+                Err(_) => stuck.push(i),
+            }
+        }
+        if !restored.is_empty() {
+            summary.push(format!("rolled back {}", names(&restored)));
+        }
+        if !stuck.is_empty() {
+            summary.push(format!("could not roll back {}", names(&stuck)));
+        }
+        left.clear();
+    } else if !succeeded.is_empty() {
+        summary.push(format!("updated {}", names(&succeeded)));
+    }
+    let code = exit_code(&failed[0].1);
+    Outcome {
+        written: written(&left),
+        result: Err(ErrorKind::DevicesFailed(
+            format!("Failed to update {} of {} devices; {}", failed.len(), backlights.len(), summary.join("; ")),
+            code).into()),
+    }
+}
@@ -0,0 +1,502 @@
+//! Settings read from `config.toml`.
+//!
+//! Two files are read if they exist: the system-wide
+//! `/etc/backctl/config.toml`, then the user's
+//! `$XDG_CONFIG_HOME/backctl/config.toml` (or `~/.config/backctl/config.toml`),
+//! whose settings win. Command-line flags win over both.
+//!
+//! ```toml
+//! step = "5%"            # what inc and dec change by when given no VALUE
+//! fade = "150ms"         # fade every inc, dec and set; --fade 0 turns it off
+//! curve = "auto"         # as --curve
+//! gamma = 2.2            # as --gamma
+//! floor = "1"            # as --floor
+//! ceiling = "100%"       # as --ceiling
+//! ignore = ["acpi_video*"]
+//!
+//! [devices.intel_backlight]   # the name is a sysname glob
+//! alias = "screen"            # so `--device screen` picks it
+//! floor = "2%"
+//! ceiling = "90%"
+//! curve = "log"               # linear, gamma or log
+//!
+//! [auto]                      # for `backctl daemon --auto`
+//! curve = "0:5,100:40,10000:100"  # lux:percent points, as --auto-curve
+//! sensor = "/sys/bus/iio/devices/iio:device0"
+//! interval = "1s"
+//! fade = "1s"
+//! smoothing = 0.3             # weight of each new sample, up to 1
+//! hysteresis = 5              # percentage points to ignore
+//!
+//! [presets.movie]             # applied with `backctl preset movie`
+//! screen = "20%"              # an alias or sysname glob, and a level
+//! kbd_backlight = "0"
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml;
+
+use als::{self, LuxCurve};
+use backlight::{Backlight, Class};
+use curve::{Curve, CurvePolicy, Scale};
+use fade::parse_duration;
+use select::{glob_match, Selector};
+use update::{Level, Limit, Update};
+use {ErrorKind, Result};
+
+/// Where the system-wide configuration lives
+pub const SYSTEM_PATH: &str = "/etc/backctl/config.toml";
+
+/// A config file as written, before its values are checked
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    step: Option<String>,
+    fade: Option<String>,
+    curve: Option<String>,
+    gamma: Option<f64>,
+    floor: Option<String>,
+    ceiling: Option<String>,
+    #[serde(default)]
+    ignore: Vec<String>,
+    #[serde(default)]
+    devices: BTreeMap<String, DeviceFile>,
+    #[serde(default)]
+    presets: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(default)]
+    auto: AutoFile,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceFile {
+    alias: Option<String>,
+    floor: Option<String>,
+    ceiling: Option<String>,
+    curve: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AutoFile {
+    curve: Option<String>,
+    sensor: Option<PathBuf>,
+    interval: Option<String>,
+    fade: Option<String>,
+    smoothing: Option<f64>,
+    hysteresis: Option<f64>,
+}
+
+/// Settings for the devices whose sysname matches `pattern`
+#[derive(Clone, Debug, Default)]
+pub struct Device {
+    pub pattern: String,
+    pub alias: Option<String>,
+    pub floor: Option<Level>,
+    pub ceiling: Option<Level>,
+    /// A curve name, resolved with the gamma in effect by `curve`
+    pub curve: Option<String>,
+}
+
+/// Names a preset can't have, being `backctl preset` subcommands
+pub const RESERVED_PRESETS: &[&str] = &["list", "save"];
+
+/// Levels to set a group of devices to in one go
+#[derive(Clone, Debug, Default)]
+pub struct Preset {
+    pub name: String,
+    /// Each entry's device alias or sysname glob, and the level as `set`
+    /// takes it
+    pub levels: Vec<(String, String)>,
+}
+
+/// How `daemon --auto` follows the ambient light sensor
+#[derive(Clone, Debug, Default)]
+pub struct Auto {
+    pub curve: Option<LuxCurve>,
+    /// The IIO device directory, rather than the first sensor found
+    pub sensor: Option<PathBuf>,
+    pub interval: Option<Duration>,
+    pub fade: Option<Duration>,
+    pub smoothing: Option<f64>,
+    pub hysteresis: Option<f64>,
+}
+
+/// Everything the config files set. Missing settings are `None` or empty,
+/// leaving the command line's defaults in place.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub step: Option<String>,
+    pub fade: Option<Duration>,
+    pub curve: Option<String>,
+    pub gamma: Option<f64>,
+    pub floor: Option<Level>,
+    pub ceiling: Option<Level>,
+    /// Sysname globs of devices backctl should act as if it can't see
+    pub ignore: Vec<String>,
+    pub devices: Vec<Device>,
+    pub presets: Vec<Preset>,
+    pub auto: Auto,
+}
+
+impl Config {
+    /// The system-wide file followed by the user's, in the order they apply
+    pub fn default_paths() -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from(SYSTEM_PATH)];
+        paths.extend(Config::user_path());
+        paths
+    }
+
+    /// `$XDG_CONFIG_HOME/backctl/config.toml` or `~/.config/backctl/config.toml`
+    pub fn user_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("backctl").join("config.toml"))
+    }
+
+    /// Reads each of `paths` that exists, later files overriding earlier
+    /// ones, failing with `InvalidConfig` at the first bad setting
+    pub fn load(paths: &[PathBuf]) -> Result<Self> {
+        let mut config = Config::default();
+        for path in paths.iter().filter(|p| p.exists()) {
+            let (file, problems) = Config::read(path)?;
+            if let Some(problem) = problems.into_iter().next() {
+                bail!(ErrorKind::InvalidConfig(path.clone(), problem));
+            }
+            config.merge(file);
+        }
+        Ok(config)
+    }
+
+    /// Reads a single file, returning the settings that are valid along
+    /// with a description of each one that isn't. Only a file that can't be
+    /// read or isn't TOML fails outright.
+    pub fn read(path: &Path) -> Result<(Self, Vec<String>)> {
+        let text = fs::read_to_string(path)?;
+        let file: File = match toml::from_str(&text) {
+            Ok(file) => file,
+            Err(e) => bail!(ErrorKind::InvalidConfig(path.to_path_buf(), e.to_string())),
+        };
+
+        let mut problems = Vec::new();
+        let gamma = check(&mut problems, "gamma", file.gamma, |g| Curve::parse("gamma", g).map(|_| g));
+        let curve = |s: &str| Curve::parse(s, gamma.unwrap_or(2.2)).map(|_| s.to_string());
+        let mut config = Config {
+            step: check(&mut problems, "step", file.step, |s| Update::inc(&s).map(|_| s)),
+            fade: check(&mut problems, "fade", file.fade, |s| parse_duration(&s)),
+            curve: check(&mut problems, "curve", file.curve, |s| if s == "auto" { Ok(s) } else { curve(&s) }),
+            gamma,
+            floor: check(&mut problems, "floor", file.floor, |s| Level::parse(&s)),
+            ceiling: check(&mut problems, "ceiling", file.ceiling, |s| Level::parse(&s)),
+            ignore: file.ignore,
+            devices: Vec::new(),
+            presets: Vec::new(),
+            auto: Auto {
+                curve: check(&mut problems, "auto.curve", file.auto.curve, |s| LuxCurve::parse(&s)),
+                sensor: file.auto.sensor,
+                interval: check(&mut problems, "auto.interval", file.auto.interval, |s| parse_duration(&s)),
+                fade: check(&mut problems, "auto.fade", file.auto.fade, |s| parse_duration(&s)),
+                smoothing: check(&mut problems, "auto.smoothing", file.auto.smoothing, als::smoothing),
+                hysteresis: check(&mut problems, "auto.hysteresis", file.auto.hysteresis, als::hysteresis),
+            },
+        };
+        for (pattern, device) in file.devices {
+            let key = |name| format!("devices.\"{}\".{}", pattern, name);
+            config.devices.push(Device {
+                alias: device.alias,
+                floor: check(&mut problems, &key("floor"), device.floor, |s| Level::parse(&s)),
+                ceiling: check(&mut problems, &key("ceiling"), device.ceiling, |s| Level::parse(&s)),
+                curve: check(&mut problems, &key("curve"), device.curve, |s| curve(&s)),
+                pattern,
+            });
+        }
+
+        for (name, levels) in file.presets {
+            if RESERVED_PRESETS.contains(&name.as_str()) {
+                problems.push(format!("presets.\"{}\" can't be applied, as `backctl preset {}` is a command", name, name));
+                continue;
+            }
+            let mut preset = Preset { name, levels: Vec::new() };
+            for (device, level) in levels {
+                let key = format!("presets.\"{}\".\"{}\"", preset.name, device);
+                if let Some(level) = check(&mut problems, &key, Some(level), |s| Update::set(&s).map(|_| s)) {
+                    preset.levels.push((device, level));
+                }
+            }
+            config.presets.push(preset);
+        }
+
+        let mut aliases: Vec<&str> = config.devices.iter().filter_map(|d| d.alias.as_deref()).collect();
+        aliases.sort_unstable();
+        for pair in aliases.windows(2).filter(|pair| pair[0] == pair[1]) {
+            problems.push(format!("alias '{}' is given to more than one device", pair[0]));
+        }
+        Ok((config, problems))
+    }
+
+    /// Applies the settings of a later file over these
+    pub fn merge(&mut self, later: Config) {
+        self.step = later.step.or(self.step.take());
+        self.fade = later.fade.or(self.fade);
+        self.curve = later.curve.or(self.curve.take());
+        self.gamma = later.gamma.or(self.gamma);
+        self.floor = later.floor.or(self.floor);
+        self.ceiling = later.ceiling.or(self.ceiling);
+        self.ignore.extend(later.ignore);
+        for device in later.devices {
+            match self.devices.iter_mut().find(|d| d.pattern == device.pattern) {
+                Some(d) => {
+                    d.alias = device.alias.or(d.alias.take());
+                    d.floor = device.floor.or(d.floor);
+                    d.ceiling = device.ceiling.or(d.ceiling);
+                    d.curve = device.curve.or(d.curve.take());
+                }
+                None => self.devices.push(device),
+            }
+        }
+        self.auto.curve = later.auto.curve.or(self.auto.curve.take());
+        self.auto.sensor = later.auto.sensor.or(self.auto.sensor.take());
+        self.auto.interval = later.auto.interval.or(self.auto.interval);
+        self.auto.fade = later.auto.fade.or(self.auto.fade);
+        self.auto.smoothing = later.auto.smoothing.or(self.auto.smoothing);
+        self.auto.hysteresis = later.auto.hysteresis.or(self.auto.hysteresis);
+        // A preset is replaced as a whole, so a user can leave out devices a
+        // system-wide preset of the same name sets
+        for preset in later.presets {
+            self.presets.retain(|p| p.name != preset.name);
+            self.presets.push(preset);
+        }
+    }
+
+    /// The device pattern `name` is an alias for, if it is one
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.devices.iter()
+            .find(|d| d.alias.as_deref() == Some(name))
+            .map(|d| d.pattern.as_str())
+    }
+
+    /// A selector for the devices a preset entry or other setting names,
+    /// which may be an alias
+    pub fn selector(&self, name: &str) -> Selector {
+        Selector { devices: vec![self.alias(name).unwrap_or(name).to_string()], ..Selector::default() }
+    }
+
+    pub fn preset(&self, name: &str) -> Option<&Preset> {
+        self.presets.iter().find(|p| p.name == name)
+    }
+
+    pub fn is_ignored(&self, backlight: &Backlight) -> bool {
+        self.ignore.iter().any(|pattern| glob_match(pattern, &backlight.sysname))
+    }
+
+    /// Settings that refer to devices not among `backlights`. They aren't
+    /// errors, since a monitor may simply be unplugged, but are often typos.
+    pub fn unmatched(&self, backlights: &[Backlight]) -> Vec<String> {
+        let matches = |pattern: &str| backlights.iter().any(|bl| glob_match(pattern, &bl.sysname));
+        let mut warnings: Vec<String> = self.devices.iter()
+            .filter(|d| !matches(&d.pattern))
+            .map(|d| format!("devices.\"{}\" matches no device", d.pattern))
+            .collect();
+        warnings.extend(self.ignore.iter()
+            .filter(|pattern| !matches(pattern))
+            .map(|pattern| format!("ignore pattern '{}' matches no device", pattern)));
+        for preset in &self.presets {
+            warnings.extend(preset.levels.iter()
+                .filter(|&(device, _)| !backlights.iter().any(|bl| self.selector(device).matches(bl)))
+                .map(|(device, _)| format!("presets.\"{}\".\"{}\" matches no device", preset.name, device)));
+        }
+        warnings
+    }
+
+    /// The curve to use. `curve` and `gamma` are the ones given on the
+    /// command line, if any, which win over the configuration; `overrides`
+    /// are the scales `auto` assumes for devices matching each glob.
+    pub fn curve_policy(&self, curve: Option<&str>, gamma: Option<f64>,
+                        overrides: Vec<(String, Scale)>) -> Result<CurvePolicy> {
+        let gamma = gamma.or(self.gamma).unwrap_or(2.2);
+        let name = curve.or(self.curve.as_deref()).unwrap_or("auto");
+        let policy = match name {
+            "auto" => CurvePolicy::Auto { perceptual: Curve::parse("gamma", gamma)?, overrides },
+            name => CurvePolicy::Fixed(Curve::parse(name, gamma)?),
+        };
+
+        // A curve given on the command line is for every device
+        let mut devices = Vec::new();
+        for device in self.devices.iter().filter(|_| curve.is_none()) {
+            if let Some(ref curve) = device.curve {
+                devices.push((device.pattern.clone(), Curve::parse(curve, gamma)?));
+            }
+        }
+        if devices.is_empty() {
+            return Ok(policy);
+        }
+        Ok(CurvePolicy::PerDevice { devices, fallback: Box::new(policy) })
+    }
+
+    /// The floor for devices of `class`, with `given` holding `[NAME=]LEVEL`
+    /// values from the command line. Without a setting, screens stop just
+    /// short of dark while keyboards and other LEDs may go off.
+    pub fn floor(&self, class: Class, given: &[&str]) -> Result<Limit> {
+        let default = match class {
+            Class::Backlight => Level::Raw(1),
+            Class::Leds => Level::Raw(0),
+        };
+        let configured = self.devices.iter().filter_map(|d| d.floor.map(|l| (d.pattern.clone(), l))).collect();
+        self.limit(given, self.floor.unwrap_or(default), configured)
+    }
+
+    /// The ceiling, with `given` holding `[NAME=]LEVEL` values from the
+    /// command line. Without a setting, devices may reach their maximum.
+    pub fn ceiling(&self, given: &[&str]) -> Result<Limit> {
+        let configured = self.devices.iter().filter_map(|d| d.ceiling.map(|l| (d.pattern.clone(), l))).collect();
+        self.limit(given, self.ceiling.unwrap_or(Level::Percent(100.0)), configured)
+    }
+
+    /// A limit of `default` for every device and `configured` levels for
+    /// some, overridden by the `[NAME=]LEVEL` values `given` on the command
+    /// line, where NAME may be an alias
+    fn limit(&self, given: &[&str], default: Level, configured: Vec<(String, Level)>) -> Result<Limit> {
+        let mut limit = Limit::new(default);
+        let mut overridden = false;
+        for value in given {
+            match value.find('=') {
+                Some(i) => {
+                    let name = &value[..i];
+                    let pattern = self.alias(name).unwrap_or(name);
+                    limit.devices.push((pattern.to_string(), Level::parse(&value[i + 1..])?));
+                }
+                None => {
+                    limit.default = Level::parse(value)?;
+                    overridden = true;
+                }
+            }
+        }
+        // A level given for every device on the command line beats the
+        // configuration's per-device ones too
+        if !overridden {
+            limit.devices.extend(configured);
+        }
+        Ok(limit)
+    }
+
+    /// The updates the preset `name` makes to the devices in `available`
+    /// that `wanted` matches, `update` turning each of its levels into an
+    /// update. A device named by more than one entry takes the first.
+    pub fn preset_updates<F>(&self, name: &str, available: &[Backlight], wanted: &Selector,
+                             update: F) -> Result<Vec<(Backlight, Update)>>
+        where F: Fn(&str) -> Result<Update>
+    {
+        let preset = match self.preset(name) {
+            Some(preset) => preset,
+            None => bail!(ErrorKind::InvalidValue(format!("No preset named '{}'; `backctl preset list` shows them", name))),
+        };
+        let mut updates: Vec<(Backlight, Update)> = Vec::new();
+        for (device, level) in &preset.levels {
+            let update = update(level)?;
+            let named = self.selector(device);
+            for bl in available.iter().filter(|bl| wanted.matches(bl) && named.matches(bl)) {
+                if !updates.iter().any(|(b, _)| b.root == bl.root) {
+                    updates.push((bl.clone(), update.clone()));
+                }
+            }
+        }
+        if updates.is_empty() {
+            bail!(ErrorKind::NoMatchingDevices(format!("preset {}", name)));
+        }
+        Ok(updates)
+    }
+
+    /// The current raw brightness of each of `backlights`, named for a
+    /// preset by their alias when it names only them and otherwise by
+    /// sysname
+    pub fn preset_levels(&self, backlights: &[Backlight]) -> Result<Vec<(String, String)>> {
+        let only = |pattern: &str| backlights.iter().filter(|b| glob_match(pattern, &b.sysname)).count() == 1;
+        let mut levels = Vec::new();
+        for bl in backlights {
+            let name = self.devices.iter()
+                .find(|d| d.alias.is_some() && glob_match(&d.pattern, &bl.sysname) && only(&d.pattern))
+                .and_then(|d| d.alias.clone())
+                .unwrap_or_else(|| bl.sysname.clone());
+            levels.push((name, bl.get_brightness()?.to_string()));
+        }
+        Ok(levels)
+    }
+}
+
+/// Parses a setting with `parse`, describing the problem under `key` and
+/// leaving it unset if it's invalid
+fn check<T, U, F>(problems: &mut Vec<String>, key: &str, value: Option<T>, parse: F) -> Option<U>
+    where F: FnOnce(T) -> Result<U>
+{
+    match value.map(parse) {
+        Some(Ok(parsed)) => Some(parsed),
+        Some(Err(e)) => {
+            problems.push(format!("{}: {}", key, e));
+            None
+        }
+        None => None,
+    }
+}
+
+/// Writes `levels` to the file at `path` as the preset `name`, replacing any
+/// preset of that name. The rest of the file is left as it was, comments
+/// and all.
+pub fn save_preset(path: &Path, name: &str, levels: &[(String, String)]) -> Result<()> {
+    if RESERVED_PRESETS.contains(&name) {
+        bail!(ErrorKind::InvalidValue(format!("A preset can't be named '{}', as `backctl preset {}` is a command", name, name)));
+    }
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let headers = [format!("[presets.{}]", name), format!("[presets.\"{}\"]", name)];
+    let mut lines = Vec::new();
+    let mut replacing = false;
+    for line in text.lines() {
+        let table = line.split('#').next().unwrap_or_default().trim();
+        if table.starts_with('[') {
+            replacing = headers.iter().any(|h| h == table);
+        }
+        if !replacing {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push_str("\n\n");
+    }
+    text.push_str(&format!("[presets.{}]\n", toml_key(name)));
+    for (device, level) in levels {
+        text.push_str(&format!("{} = \"{}\"\n", toml_key(device), level));
+    }
+    // Never leave behind a file that every later command would refuse
+    if let Err(e) = toml::from_str::<File>(&text) {
+        bail!(ErrorKind::InvalidConfig(path.to_path_buf(), e.to_string()));
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// `key` as a TOML key, quoted unless it's a bare key
+fn toml_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return key.to_string();
+    }
+    format!("\"{}\"", key.replace('\\', "\\\\").replace('"', "\\\""))
+}
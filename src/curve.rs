@@ -0,0 +1,130 @@
+//! Mappings between percentages and raw brightness units
+
+use std::fmt;
+
+use backlight::Backlight;
+use select::glob_match;
+use {ErrorKind, Result};
+
+/// The kernel's description of a backlight's `brightness` scale
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scale {
+    /// Brightness is proportional to emitted light
+    Linear,
+    /// Brightness already follows a perceptual curve
+    NonLinear,
+    Unknown,
+}
+
+impl Scale {
+    /// Parses a scale as the kernel writes it
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "linear" => Ok(Scale::Linear),
+            "non-linear" => Ok(Scale::NonLinear),
+            "unknown" => Ok(Scale::Unknown),
+            _ => bail!(ErrorKind::InvalidValue(
+                format!("Unknown scale '{}', expected linear, non-linear or unknown", s))),
+        }
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Scale::Linear => "linear",
+            Scale::NonLinear => "non-linear",
+            Scale::Unknown => "unknown",
+        })
+    }
+}
+
+/// How a percentage maps onto a device's raw brightness range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Curve {
+    /// Percent is a straight fraction of `max_brightness`
+    Linear,
+    /// `raw = max * (percent / 100) ^ gamma`
+    Gamma(f64),
+    /// `raw = (max + 1) ^ (percent / 100) - 1`, so each percent is the same ratio
+    Logarithmic,
+}
+
+impl Curve {
+    /// Parses a curve name, using `gamma` as the exponent for `gamma`
+    pub fn parse(name: &str, gamma: f64) -> Result<Self> {
+        match name {
+            "linear" => Ok(Curve::Linear),
+            "gamma" => {
+                if !(gamma.is_finite() && gamma > 0.0) {
+                    bail!(ErrorKind::InvalidValue(format!("Gamma exponent must be positive, got {}", gamma)));
+                }
+                Ok(Curve::Gamma(gamma))
+            }
+            "log" | "logarithmic" => Ok(Curve::Logarithmic),
+            _ => bail!(ErrorKind::InvalidValue(format!("Unknown curve '{}', expected linear, gamma or log", name))),
+        }
+    }
+
+    /// Raw brightness for `percent` (0 to 100) of the way up the curve
+    pub fn to_raw(self, percent: f64, max: u32) -> u32 {
+        let fraction = (percent / 100.0).clamp(0.0, 1.0);
+        let max = f64::from(max);
+        let raw = match self {
+            Curve::Linear => max * fraction,
+            Curve::Gamma(gamma) => max * fraction.powf(gamma),
+            Curve::Logarithmic => (max + 1.0).powf(fraction) - 1.0,
+        };
+        raw.round().clamp(0.0, max) as u32
+    }
+
+    /// Position of `raw` along the curve as a percentage (0 to 100)
+    pub fn to_percent(self, raw: u32, max: u32) -> f64 {
+        if max == 0 {
+            return 0.0;
+        }
+        let fraction = (f64::from(raw) / f64::from(max)).min(1.0);
+        let percent = match self {
+            Curve::Linear => fraction,
+            Curve::Gamma(gamma) => fraction.powf(1.0 / gamma),
+            Curve::Logarithmic => (f64::from(raw) + 1.0).ln() / (f64::from(max) + 1.0).ln(),
+        };
+        percent * 100.0
+    }
+}
+
+/// Chooses the curve used for each device
+#[derive(Clone, Debug)]
+pub enum CurvePolicy {
+    /// Use the same curve for every device
+    Fixed(Curve),
+    /// Use `perceptual` for devices whose `scale` is linear and a linear
+    /// mapping otherwise. `overrides` replaces the reported scale for devices
+    /// whose sysname matches the glob, for drivers that report `unknown`.
+    Auto { perceptual: Curve, overrides: Vec<(String, Scale)> },
+    /// Use the curve given for devices whose sysname matches the glob, and
+    /// `fallback` for the rest
+    PerDevice { devices: Vec<(String, Curve)>, fallback: Box<CurvePolicy> },
+}
+
+impl CurvePolicy {
+    /// The curve to use for `backlight`
+    pub fn resolve(&self, backlight: &Backlight) -> Curve {
+        match *self {
+            CurvePolicy::Fixed(curve) => curve,
+            CurvePolicy::Auto { perceptual, ref overrides } => {
+                let scale = overrides.iter()
+                    .find(|&(pattern, _)| glob_match(pattern, &backlight.sysname))
+                    .map(|&(_, scale)| scale)
+                    .unwrap_or_else(|| backlight.get_scale());
+                match scale {
+                    Scale::Linear => perceptual,
+                    Scale::NonLinear | Scale::Unknown => Curve::Linear,
+                }
+            }
+            CurvePolicy::PerDevice { ref devices, ref fallback } => devices.iter()
+                .find(|&(pattern, _)| glob_match(pattern, &backlight.sysname))
+                .map_or_else(|| fallback.resolve(backlight), |&(_, curve)| curve),
+        }
+    }
+}
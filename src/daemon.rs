@@ -0,0 +1,149 @@
+//! A long-running process that owns the backlights and serves requests
+//! over a Unix socket.
+//!
+//! The protocol is line based. A request is a single line holding the
+//! command-line arguments of a backctl invocation (without the program name)
+//! separated by tabs. The daemon answers with any number of lines tagged
+//! `out <text>`, which are the command's standard output, followed by either
+//! `ok` or `fail <code> <message>`, where `code` is the exit status the
+//! command would have had run directly. A failure may be preceded by
+//! `hint <text>` lines, which together make up the error's hint.
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use {exit_code, hint, Error, ErrorKind, Result};
+
+/// How long a client has to send its request, and to take each line of the
+/// answer, before the daemon gives up on it and serves the next
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `$XDG_RUNTIME_DIR/backctl.sock`, or `/run/backctl.sock` for a system daemon
+pub fn default_socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("backctl.sock"),
+        None => PathBuf::from("/run/backctl.sock"),
+    }
+}
+
+/// Encodes arguments as a request line, or `None` if they can't be sent
+/// unambiguously
+pub fn encode_request<I: IntoIterator<Item = String>>(args: I) -> Option<String> {
+    let args: Vec<String> = args.into_iter().collect();
+    if args.iter().any(|a| a.contains('\t') || a.contains('\n')) {
+        return None;
+    }
+    Some(args.join("\t"))
+}
+
+/// Splits a request line back into arguments
+pub fn decode_request(line: &str) -> Vec<String> {
+    if line.is_empty() {
+        return Vec::new();
+    }
+    line.split('\t').map(String::from).collect()
+}
+
+/// A connection to a running daemon
+pub struct Client {
+    stream: UnixStream,
+}
+
+impl Client {
+    /// Connects to a running daemon, or returns `None` if there isn't one
+    pub fn connect(path: &Path) -> Option<Self> {
+        UnixStream::connect(path).ok().map(|stream| Client { stream })
+    }
+
+    /// Sends a request and copies the command's output to `out`
+    pub fn forward(self, request: &str, out: &mut dyn Write) -> Result<()> {
+        let mut stream = self.stream;
+        writeln!(stream, "{}", request)?;
+        stream.flush()?;
+        let mut hint: Vec<String> = Vec::new();
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if let Some(text) = line.strip_prefix("out ") {
+                writeln!(out, "{}", text)?;
+            } else if let Some(text) = line.strip_prefix("hint ") {
+                hint.push(text.to_string());
+            } else if line == "ok" {
+                return Ok(());
+            } else if let Some(failure) = line.strip_prefix("fail ") {
+                let mut parts = failure.splitn(2, ' ');
+                let code = parts.next().and_then(|c| c.parse().ok()).unwrap_or(1);
+                let hint = if hint.is_empty() { None } else { Some(hint.join("\n")) };
+                bail!(ErrorKind::Forwarded(parts.next().unwrap_or_default().to_string(), code, hint));
+            } else {
+                bail!("Unexpected response from daemon: {}", line);
+            }
+        }
+        bail!("Daemon closed the connection without responding")
+    }
+}
+
+/// Listens on `path` and answers requests one at a time, so concurrent
+/// invocations are applied in order instead of racing each other. A
+/// connection that can't be accepted or answered is handed to `failed`.
+pub fn serve<F, E>(path: &Path, mut handler: F, mut failed: E) -> Result<()>
+    where F: FnMut(&[String], &mut Vec<u8>) -> Result<()>,
+          E: FnMut(Error)
+{
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!("A daemon is already listening on {}", path.display());
+        }
+        // Left behind by a daemon that didn't shut down cleanly
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                failed(e.into());
+                continue;
+            }
+        };
+        if let Err(e) = respond(stream, &mut handler) {
+            failed(e);
+        }
+    }
+    Ok(())
+}
+
+fn respond<F>(stream: UnixStream, handler: &mut F) -> Result<()>
+    where F: FnMut(&[String], &mut Vec<u8>) -> Result<()>
+{
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut line = String::new();
+    // Connecting and hanging up is how a second daemon checks for this one
+    if BufReader::new(&stream).read_line(&mut line)? == 0 {
+        return Ok(());
+    }
+    let args = decode_request(line.trim_end_matches('\n'));
+
+    let mut output = Vec::new();
+    let result = handler(&args, &mut output);
+
+    let mut stream = stream;
+    for text in String::from_utf8_lossy(&output).lines() {
+        writeln!(stream, "out {}", text)?;
+    }
+    match result {
+        Ok(()) => writeln!(stream, "ok")?,
+        Err(e) => {
+            for text in hint(&e).iter().flat_map(|h| h.lines()) {
+                writeln!(stream, "hint {}", text)?;
+            }
+            writeln!(stream, "fail {} {}", exit_code(&e), e.to_string().replace('\n', " "))?
+        }
+    }
+    Ok(())
+}
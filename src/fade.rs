@@ -0,0 +1,140 @@
+//! Gradual brightness transitions
+
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use backlight::Backlight;
+use {Error, ErrorKind, Result};
+
+/// How intermediate values are spread over a fade
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    /// Starts and ends slowly, fastest in the middle
+    EaseInOut,
+    /// Changes by a constant ratio per frame, which looks even to the eye
+    Exponential,
+}
+
+impl Easing {
+    /// Brightness at fraction `t` (0 to 1) of the way from `start` to `end`
+    fn interpolate(&self, start: u32, end: u32, t: f64) -> u32 {
+        let (start, end) = (f64::from(start), f64::from(end));
+        let value = match *self {
+            Easing::Linear => start + (end - start) * t,
+            Easing::EaseInOut => {
+                let eased = if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                };
+                start + (end - start) * eased
+            }
+            // Offset by one so fades to and from zero are still defined
+            Easing::Exponential => (start + 1.0) * ((end + 1.0) / (start + 1.0)).powf(t) - 1.0,
+        };
+        value.round().max(0.0) as u32
+    }
+}
+
+impl FromStr for Easing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "linear" => Ok(Easing::Linear),
+            "ease-in-out" => Ok(Easing::EaseInOut),
+            "exponential" => Ok(Easing::Exponential),
+            _ => bail!(ErrorKind::InvalidValue(
+                format!("Unknown easing '{}', expected linear, ease-in-out or exponential", s))),
+        }
+    }
+}
+
+/// Parses durations such as `400ms`, `1.5s` or a bare number of milliseconds
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 1.0)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, 1000.0)
+    } else {
+        (s, 1.0)
+    };
+    let millis: f64 = match number.trim().parse() {
+        Ok(n) => n,
+        Err(_) => bail!(ErrorKind::InvalidValue(format!("Invalid duration '{}'", s))),
+    };
+    if !millis.is_finite() || millis < 0.0 {
+        bail!(ErrorKind::InvalidValue(format!("Invalid duration '{}'", s)));
+    }
+    Ok(Duration::from_micros((millis * scale * 1000.0) as u64))
+}
+
+/// A gradual transition to new brightness values
+pub struct Fade {
+    pub duration: Duration,
+    /// Frames per second
+    pub rate: u32,
+    pub easing: Easing,
+}
+
+impl Fade {
+    /// Moves every backlight from its current brightness to its target,
+    /// returning how each device fared.
+    ///
+    /// All devices are stepped together on the same frame clock so they
+    /// arrive at the same time, and the final frame always writes the exact
+    /// target. A device that fails is left where it got to while the rest
+    /// carry on.
+    pub fn run(&self, targets: &[(Backlight, u32)]) -> Vec<Result<()>> {
+        self.run_until(targets, || false)
+    }
+
+    /// Like `run`, but stops where it got to as soon as `cancelled` returns
+    /// true, which is checked before every frame
+    pub fn run_until<F: Fn() -> bool>(&self, targets: &[(Backlight, u32)], cancelled: F) -> Vec<Result<()>> {
+        let mut results: Vec<Result<()>> = Vec::with_capacity(targets.len());
+        let mut starts = Vec::with_capacity(targets.len());
+        for (bl, _) in targets {
+            let start = bl.get_brightness();
+            starts.push(*start.as_ref().unwrap_or(&0));
+            results.push(start.map(|_| ()));
+        }
+        let mut current = starts.clone();
+
+        let frames = (self.duration.as_secs_f64() * f64::from(self.rate.max(1))).ceil().max(1.0) as u32;
+        let begin = Instant::now();
+        for frame in 1..=frames {
+            // Sleep until this frame's deadline rather than for a fixed
+            // interval so slow writes don't stretch the fade
+            let deadline = begin + self.duration.mul_f64(f64::from(frame) / f64::from(frames));
+            let now = Instant::now();
+            if deadline > now {
+                thread::sleep(deadline - now);
+            }
+
+            if cancelled() {
+                break;
+            }
+
+            let t = f64::from(frame) / f64::from(frames);
+            for (i, &(ref bl, target)) in targets.iter().enumerate() {
+                if results[i].is_err() {
+                    continue;
+                }
+                let value = if frame == frames {
+                    target
+                } else {
+                    self.easing.interpolate(starts[i], target, t)
+                };
+                if value != current[i] {
+                    results[i] = bl.set_brightness(value);
+                    current[i] = value;
+                }
+            }
+        }
+        results
+    }
+}
@@ -0,0 +1,148 @@
+//! Following backlights as they appear and disappear
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use libc;
+use udev;
+
+use backlight::{Backlight, Class, Source};
+use Result;
+
+/// How often a `Source::Sysfs` tree is rescanned, since plain directories
+/// can't be monitored through udev
+const RESCAN_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A change in the devices present
+pub enum Event {
+    Added(Backlight),
+    /// The syspath of a backlight that went away
+    Removed(PathBuf),
+}
+
+/// Follows devices being added and removed. The watch starts when it's
+/// made, so a caller that enumerates devices afterwards and then runs it
+/// misses nothing that appears in between.
+pub struct Watcher {
+    inner: Inner,
+}
+
+enum Inner {
+    Udev(udev::MonitorSocket),
+    Dirs {
+        dirs: Vec<PathBuf>,
+        /// Entries in the class directories, mapped to the device they
+        /// resolved to
+        known: HashMap<PathBuf, PathBuf>,
+    },
+}
+
+// libudev objects aren't tied to the thread that made them, they just can't
+// be used from two at once, and a Watcher is only ever used by its owner
+unsafe impl Send for Watcher {}
+
+impl Watcher {
+    /// Starts watching `source` for devices in `classes`
+    pub fn new(source: &Source, classes: &[Class]) -> Result<Self> {
+        let inner = match *source {
+            Source::Udev => Inner::Udev(monitor(classes)?),
+            Source::Sysfs(ref root) => {
+                let dirs: Vec<PathBuf> = classes.iter().map(|c| root.join("class").join(c.subsystem())).collect();
+                let mut known = HashMap::new();
+                rescan(&dirs, &mut known, &mut |_| {})?;
+                Inner::Dirs { dirs, known }
+            }
+        };
+        Ok(Watcher { inner })
+    }
+
+    /// Calls `on_event` for every device added or removed since the watch
+    /// started. Only returns on error.
+    pub fn run<F: FnMut(Event)>(self, mut on_event: F) -> Result<()> {
+        match self.inner {
+            Inner::Udev(socket) => watch_udev(socket, on_event),
+            Inner::Dirs { dirs, mut known } => loop {
+                thread::sleep(RESCAN_INTERVAL);
+                rescan(&dirs, &mut known, &mut on_event)?;
+            },
+        }
+    }
+}
+
+/// A udev monitor for the subsystems of `classes`
+pub fn monitor(classes: &[Class]) -> Result<udev::MonitorSocket> {
+    let context = udev::Context::new()?;
+    let mut builder = udev::MonitorBuilder::new(&context)?;
+    for class in classes {
+        builder.match_subsystem(class.subsystem())?;
+    }
+    Ok(builder.listen()?)
+}
+
+fn watch_udev<F: FnMut(Event)>(mut socket: udev::MonitorSocket, mut on_event: F) -> Result<()> {
+    loop {
+        wait_readable(socket.as_raw_fd(), None)?;
+        // The monitor socket is non-blocking, so this drains what's queued
+        for event in socket.by_ref() {
+            match event.event_type() {
+                udev::EventType::Add => on_event(Event::Added(Backlight::from_device(&event.device()))),
+                udev::EventType::Remove => on_event(Event::Removed(PathBuf::from(event.syspath()))),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Blocks until `fd` is readable or `timeout` passes, returning whether it
+/// became readable
+pub fn wait_readable(fd: RawFd, timeout: Option<Duration>) -> Result<bool> {
+    let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+    let mut fds = [libc::pollfd { fd, events: libc::POLLIN, revents: 0 }];
+    loop {
+        match unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) } {
+            n if n >= 0 => return Ok(n > 0),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+}
+
+/// Reports the devices that appeared in or vanished from `dirs` since
+/// `known` was last brought up to date
+fn rescan<F: FnMut(Event)>(dirs: &[PathBuf], known: &mut HashMap<PathBuf, PathBuf>,
+                           on_event: &mut F) -> Result<()> {
+    let mut present = Vec::new();
+    for dir in dirs.iter().filter(|d| d.is_dir()) {
+        for entry in fs::read_dir(dir)? {
+            present.push(entry?.path());
+        }
+    }
+    present.sort();
+
+    let gone: Vec<PathBuf> = known.keys().filter(|p| !present.contains(p)).cloned().collect();
+    for path in gone {
+        if let Some(root) = known.remove(&path) {
+            on_event(Event::Removed(root));
+        }
+    }
+    for path in present {
+        // Skip devices that are still being populated
+        if known.contains_key(&path) || !path.join("max_brightness").exists() {
+            continue;
+        }
+        if let Ok(bl) = Backlight::from_dir(&path) {
+            known.insert(path, bl.root.clone());
+            on_event(Event::Added(bl));
+        }
+    }
+    Ok(())
+}
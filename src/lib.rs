@@ -0,0 +1,159 @@
+//! Backlight control through sysfs and udev.
+//!
+//! This is the library behind the `backctl` command. It finds backlight and
+//! LED devices ([`Backlights`]), reads and writes their brightness
+//! ([`Backlight`]), picks devices the way the command line does
+//! ([`Selector`]) and applies relative or absolute changes along a perceptual
+//! curve ([`Update`]).
+//!
+//! ```no_run
+//! extern crate backctl;
+//!
+//! use backctl::{Backlights, Class, Source, Update};
+//!
+//! # fn main() -> backctl::Result<()> {
+//! let update = Update::inc("10%")?;
+//! for backlight in Backlights::new(&Source::Udev, &[Class::Backlight])? {
+//!     update.apply(backlight)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`config::Config`] resolves the configuration files into curves, limits
+//! and presets, and [`batch`] writes several devices at once the way the
+//! command does.
+//!
+//! Everything fails with the [`Error`] generated by `error_chain`; its
+//! [`ErrorKind`] tells a missing device from a permission problem, and
+//! [`exit_code`] maps it to the status the command exits with.
+
+extern crate udev;
+extern crate libc;
+#[macro_use]
+extern crate error_chain;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+
+use std::{io, num};
+use std::path::PathBuf;
+
+pub mod als;
+pub mod backlight;
+pub mod batch;
+pub mod config;
+pub mod curve;
+pub mod daemon;
+pub mod fade;
+pub mod hotplug;
+mod logind;
+pub mod output;
+pub mod power;
+pub mod select;
+pub mod state;
+pub mod update;
+pub mod watch;
+
+pub use backlight::{Backend, Backlight, Backlights, Class, Connector, Source};
+pub use config::Config;
+pub use curve::{Curve, CurvePolicy, Scale};
+pub use select::Selector;
+pub use update::{Level, Limit, Update, Verify};
+
+error_chain! {
+    foreign_links {
+        Udev(::udev::Error);
+        Io(::io::Error);
+        ParseInt(::num::ParseIntError);
+        ParseFloat(::num::ParseFloatError);
+    }
+
+    errors {
+        InvalidValue(message: String) {
+            description("invalid value")
+            display("{}", message)
+        }
+        InvalidConfig(path: PathBuf, message: String) {
+            description("invalid configuration")
+            display("Invalid configuration in {}: {}", path.display(), message)
+        }
+        NoMatchingDevices(selector: String) {
+            description("no backlight devices matched")
+            display("No backlight devices matched {}", selector)
+        }
+        PermissionDenied(path: PathBuf) {
+            description("permission denied")
+            display("Permission denied writing {}", path.display())
+        }
+        DeviceVanished(path: PathBuf) {
+            description("the device went away")
+            display("The device went away while using {}", path.display())
+        }
+        WriteRejected(path: PathBuf, value: u32) {
+            description("the driver rejected the write")
+            display("The driver rejected {} written to {}", value, path.display())
+        }
+        BrightnessNotReached(device: String, requested: u32, actual: u32) {
+            description("the hardware did not reach the requested brightness")
+            display("{} reports brightness {} after {} was written", device, actual, requested)
+        }
+        DevicesFailed(summary: String, code: i32) {
+            description("some devices could not be updated")
+            display("{}", summary)
+        }
+        Forwarded(message: String, code: i32, hint: Option<String>) {
+            description("the daemon failed the request")
+            display("{}", message)
+        }
+    }
+}
+
+/// The exit status the `backctl` binary uses for an error, as listed in the
+/// README. When only some devices fail, it's the status of the first failure.
+///
+/// | code | meaning                                      |
+/// |------|----------------------------------------------|
+/// | 1    | any other failure                            |
+/// | 2    | invalid arguments, values or configuration   |
+/// | 3    | no devices matched                           |
+/// | 4    | permission denied                            |
+/// | 5    | a device went away                           |
+/// | 6    | the driver rejected a write                  |
+/// | 7    | `--verify` saw the hardware ignore a write   |
+pub fn exit_code(e: &Error) -> i32 {
+    match *e.kind() {
+        ErrorKind::InvalidValue(..) | ErrorKind::InvalidConfig(..) => 2,
+        ErrorKind::NoMatchingDevices(..) => 3,
+        ErrorKind::PermissionDenied(..) => 4,
+        ErrorKind::DeviceVanished(..) => 5,
+        ErrorKind::WriteRejected(..) => 6,
+        ErrorKind::BrightnessNotReached(..) => 7,
+        ErrorKind::DevicesFailed(_, code) | ErrorKind::Forwarded(_, code, _) => code,
+        _ => 1,
+    }
+}
+
+/// What the user might do about an error, which the `backctl` binary
+/// prints after it
+pub fn hint(e: &Error) -> Option<String> {
+    match *e.kind() {
+        ErrorKind::InvalidConfig(..) => Some("`backctl config check` lists every problem with the configuration".to_string()),
+        ErrorKind::NoMatchingDevices(..) => Some("`backctl list` shows the devices available".to_string()),
+        ErrorKind::PermissionDenied(..) => Some(
+            "run backctl from a logind session, or let the video group write brightness with a udev rule:\n  \
+             ACTION==\"add\", SUBSYSTEM==\"backlight|leds\", \
+             RUN+=\"/bin/chgrp video %S%p/brightness\", RUN+=\"/bin/chmod g+w %S%p/brightness\"".to_string()),
+        ErrorKind::DeviceVanished(..) => {
+            Some("the device was unplugged or its driver unloaded; `backctl list` shows what is left".to_string())
+        }
+        ErrorKind::WriteRejected(..) => Some("values above max_brightness, or that the driver doesn't \
+                                               support, are refused".to_string()),
+        ErrorKind::BrightnessNotReached(..) => Some("firmware or an ambient light sensor may be overriding \
+                                                      the brightness; a longer --verify-timeout gives slow \
+                                                      panels more time".to_string()),
+        ErrorKind::Forwarded(_, _, ref hint) => hint.clone(),
+        _ => None,
+    }
+}
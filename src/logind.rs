@@ -0,0 +1,307 @@
+//! Setting brightness through systemd-logind, for users without write
+//! access to sysfs.
+//!
+//! logind lets the owner of an active session change backlights with
+//! `org.freedesktop.login1.Session.SetBrightness`. Rather than pull in a
+//! D-Bus library for one call, this speaks just enough of the wire protocol
+//! to authenticate, say hello and make that call.
+
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use libc;
+
+use Result;
+
+const DEFAULT_SYSTEM_BUS: &str = "/var/run/dbus/system_bus_socket";
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// The system bus connection, shared so fades don't reconnect every frame
+static BUS: Mutex<Option<Bus>> = Mutex::new(None);
+
+/// Asks logind to set the brightness of `subsystem`/`sysname` for the
+/// caller's session
+pub fn set_brightness(subsystem: &str, sysname: &str, value: u32) -> Result<()> {
+    let mut bus = BUS.lock().unwrap_or_else(|e| e.into_inner());
+    if bus.is_none() {
+        *bus = Some(Bus::connect()?);
+    }
+    let result = bus.as_mut().unwrap().set_brightness(subsystem, sysname, value);
+    if let Err(e) = result {
+        // Start over next time rather than reuse a connection in an unknown state
+        *bus = None;
+        bail!("logind could not set the brightness of {}: {}", sysname, e);
+    }
+    Ok(())
+}
+
+/// Little-endian D-Bus marshalling into a buffer that starts 8-aligned
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Writer { buf: Vec::new() }
+    }
+
+    fn align(&mut self, n: usize) {
+        while !self.buf.len().is_multiple_of(n) {
+            self.buf.push(0);
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.buf.push(b);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, s: &str) {
+        self.byte(s.len() as u8);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// A header field holding a string-like value of type `sig`
+    fn field(&mut self, code: u8, sig: &str, value: &str) {
+        self.align(8);
+        self.byte(code);
+        self.signature(sig);
+        if sig == "g" {
+            self.signature(value);
+        } else {
+            self.string(value);
+        }
+    }
+}
+
+/// D-Bus unmarshalling of either byte order
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn align(&mut self, n: usize) {
+        self.pos = self.pos.div_ceil(n) * n;
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.pos + n > self.buf.len() {
+            bail!("Truncated D-Bus message");
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.align(4);
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(s)
+    }
+
+    fn signature(&mut self) -> Result<String> {
+        let len = self.byte()? as usize;
+        let s = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(s)
+    }
+}
+
+/// The parts of a reply we care about
+struct Reply {
+    kind: u8,
+    reply_serial: Option<u32>,
+    error_name: Option<String>,
+    /// The first string in the body, which for errors is the message
+    message: Option<String>,
+}
+
+struct Bus {
+    stream: BufReader<UnixStream>,
+    serial: u32,
+}
+
+impl Bus {
+    /// `$DBUS_SYSTEM_BUS_ADDRESS` if it names a `unix:path=`, otherwise the
+    /// standard system bus socket
+    fn address() -> PathBuf {
+        env::var("DBUS_SYSTEM_BUS_ADDRESS").ok()
+            .and_then(|address| {
+                address.split(';')
+                    .filter_map(|a| a.strip_prefix("unix:"))
+                    .flat_map(|params| params.split(','))
+                    .filter_map(|p| p.strip_prefix("path="))
+                    .map(PathBuf::from)
+                    .next()
+            })
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SYSTEM_BUS))
+    }
+
+    fn connect() -> Result<Self> {
+        let mut stream = UnixStream::connect(Bus::address())?;
+        let uid = unsafe { libc::getuid() }.to_string();
+        let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+
+        let mut bus = Bus { stream: BufReader::new(stream), serial: 0 };
+        let mut line = String::new();
+        bus.stream.read_line(&mut line)?;
+        if !line.starts_with("OK ") {
+            bail!("System bus refused authentication: {}", line.trim());
+        }
+        bus.stream.get_mut().write_all(b"BEGIN\r\n")?;
+
+        bus.call("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus",
+                 "Hello", "", &[])?;
+        Ok(bus)
+    }
+
+    fn set_brightness(&mut self, subsystem: &str, sysname: &str, value: u32) -> Result<()> {
+        let mut body = Writer::new();
+        body.string(subsystem);
+        body.string(sysname);
+        body.u32(value);
+        self.call("org.freedesktop.login1", "/org/freedesktop/login1/session/auto",
+                  "org.freedesktop.login1.Session", "SetBrightness", "ssu", &body.buf)
+    }
+
+    /// Makes a method call and waits for its reply, skipping any signals
+    /// the bus sends in the meantime
+    fn call(&mut self, destination: &str, path: &str, interface: &str, member: &str,
+            signature: &str, body: &[u8]) -> Result<()> {
+        self.serial += 1;
+        let serial = self.serial;
+
+        let mut msg = Writer::new();
+        msg.byte(b'l');
+        msg.byte(METHOD_CALL);
+        msg.byte(0);
+        msg.byte(1);
+        msg.u32(body.len() as u32);
+        msg.u32(serial);
+        msg.u32(0);
+        let fields_start = msg.buf.len();
+        msg.field(FIELD_PATH, "o", path);
+        msg.field(FIELD_INTERFACE, "s", interface);
+        msg.field(FIELD_MEMBER, "s", member);
+        msg.field(FIELD_DESTINATION, "s", destination);
+        if !signature.is_empty() {
+            msg.field(FIELD_SIGNATURE, "g", signature);
+        }
+        let fields_len = (msg.buf.len() - fields_start) as u32;
+        msg.buf[12..16].copy_from_slice(&fields_len.to_le_bytes());
+        msg.align(8);
+        msg.buf.extend_from_slice(body);
+        self.stream.get_mut().write_all(&msg.buf)?;
+
+        loop {
+            let reply = self.receive()?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            match reply.kind {
+                METHOD_RETURN => return Ok(()),
+                ERROR => bail!("{}: {}", reply.error_name.unwrap_or_default(),
+                               reply.message.unwrap_or_default()),
+                _ => {}
+            }
+        }
+    }
+
+    fn receive(&mut self) -> Result<Reply> {
+        let mut fixed = [0; 16];
+        self.stream.read_exact(&mut fixed)?;
+        let big_endian = match fixed[0] {
+            b'l' => false,
+            b'B' => true,
+            b => bail!("Invalid D-Bus byte order marker {:#x}", b),
+        };
+        let mut header = Reader { buf: &fixed, pos: 4, big_endian };
+        let body_len = header.u32()? as usize;
+        header.u32()?;
+        let fields_len = header.u32()? as usize;
+
+        // The fields are padded out to 8 bytes before the body starts
+        let padded = (16 + fields_len).div_ceil(8) * 8 - 16;
+        let mut rest = vec![0; padded + body_len];
+        self.stream.read_exact(&mut rest)?;
+        let mut buf = fixed.to_vec();
+        buf.extend_from_slice(&rest);
+
+        let mut reply = Reply { kind: fixed[1], reply_serial: None, error_name: None, message: None };
+        let mut fields = Reader { buf: &buf[..16 + fields_len], pos: 16, big_endian };
+        let mut body_signature = String::new();
+        while fields.pos < 16 + fields_len {
+            fields.align(8);
+            let code = fields.byte()?;
+            let sig = fields.signature()?;
+            match sig.as_str() {
+                "u" => {
+                    let v = fields.u32()?;
+                    if code == FIELD_REPLY_SERIAL {
+                        reply.reply_serial = Some(v);
+                    }
+                }
+                "s" | "o" => {
+                    let v = fields.string()?;
+                    if code == FIELD_ERROR_NAME {
+                        reply.error_name = Some(v);
+                    }
+                }
+                "g" => {
+                    let v = fields.signature()?;
+                    if code == FIELD_SIGNATURE {
+                        body_signature = v;
+                    }
+                }
+                // Unix fd counts and the like; nothing we need follows them
+                _ => break,
+            }
+        }
+
+        if body_signature.starts_with('s') {
+            let mut body = Reader { buf: &buf, pos: 16 + padded, big_endian };
+            reply.message = body.string().ok();
+        }
+        Ok(reply)
+    }
+}
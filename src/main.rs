@@ -1,158 +1,1025 @@
 //! Quick and simple backlight control using udev
 
-extern crate udev;
+extern crate backctl;
 extern crate clap;
 #[macro_use]
 extern crate error_chain;
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-use std::{fs, io, num};
-use std::io::{Write, Read};
+use std::{env, io, iter, process, thread};
+use std::collections::HashMap;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-error_chain! {
-    foreign_links {
-        Udev(::udev::Error);
-        Io(::io::Error);
-        ParseInt(::num::ParseIntError);
+use backctl::{als, batch, daemon, fade, hotplug, output, power, select};
+use backctl::als::{Auto, Learned, LuxCurve, Sensor, Smoother};
+use backctl::{exit_code, hint, Error, ErrorKind, Result};
+use backctl::backlight::{Backend, Backlight, Backlights, Class, Source};
+use backctl::config::{self, Config};
+use backctl::curve::{CurvePolicy, Scale};
+use backctl::fade::Fade;
+use backctl::output::{Format, Report};
+use backctl::select::Selector;
+use backctl::state::StateDir;
+use backctl::update::{Level, Limit, Update, Verify};
+use backctl::watch::Watch;
+
+/// Whether `name` was given on the command line, rather than defaulted
+fn given(matches: &ArgMatches, name: &str) -> bool {
+    matches.occurrences_of(name) > 0
+}
+
+/// Parses the value of a required or defaulted argument
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<T> {
+    let value = matches.value_of(name).unwrap();
+    value.parse().map_err(|_| ErrorKind::InvalidValue(format!("Invalid --{} '{}'", name, value)).into())
+}
+
+/// Commands a running daemon answers in place of the CLI
+const FORWARDED: &[&str] = &["inc", "dec", "set", "get"];
+
+/// Flags that decide which devices a command sees and how it writes them,
+/// which the daemon settled when it started
+const DAEMON_FLAGS: &[&str] = &["sysfs-root", "backend", "config", "state-dir"];
+
+/// Refuses a forwarded request that gives any of `DAEMON_FLAGS` a value
+/// other than the one the daemon runs with, rather than quietly acting on
+/// the daemon's devices instead
+fn check_request(daemon: &ArgMatches, request: &ArgMatches) -> Result<()> {
+    for &flag in DAEMON_FLAGS {
+        if given(request, flag) && request.value_of_os(flag) != daemon.value_of_os(flag) {
+            bail!(ErrorKind::InvalidValue(format!(
+                "The daemon runs with a different --{}; add --no-daemon to run the command directly", flag)));
+        }
     }
+    Ok(())
 }
 
-struct Backlight {
-    root: PathBuf,
+fn get(backlights: Vec<Backlight>, curve: &CurvePolicy, actual: bool, raw: bool, percent: bool,
+       format: Format, out: &mut dyn Write) -> Result<()> {
+    if format != Format::Text {
+        let mut reports = Vec::new();
+        for bl in &backlights {
+            let value = if actual { bl.get_actual_brightness()? } else { bl.get_brightness()? };
+            reports.push(Report::new(bl, value, curve)?);
+        }
+        return output::write(format, &reports, out);
+    }
+    // Neither flag means both
+    let (raw, percent) = if raw || percent { (raw, percent) } else { (true, true) };
+    let named = backlights.len() > 1;
+    for bl in backlights {
+        let value = if actual { bl.get_actual_brightness()? } else { bl.get_brightness()? };
+        let mut fields = Vec::new();
+        if named {
+            fields.push(bl.sysname.clone());
+        }
+        if raw {
+            fields.push(value.to_string());
+        }
+        if percent {
+            let percent = curve.resolve(&bl).to_percent(value, bl.get_max_brightness()?);
+            fields.push(format!("{}%", percent.round()));
+        }
+        writeln!(out, "{}", fields.join(" "))?;
+    }
+    Ok(())
 }
 
-impl Backlight {
-    fn new(path: &Path) -> Self {
-        Backlight { root: PathBuf::from(path) }
+fn list(backlights: Vec<Backlight>, curve: &CurvePolicy, format: Format, out: &mut dyn Write) -> Result<()> {
+    if format != Format::Text {
+        let mut reports = Vec::new();
+        for bl in &backlights {
+            let mut report = Report::new(bl, bl.get_brightness()?, curve)?;
+            report.actual = bl.get_actual_brightness().ok();
+            reports.push(report);
+        }
+        return output::write(format, &reports, out);
     }
+    writeln!(out, "{:<20} {:<10} {:>10} {:>10} {:>10} {:<10} {:<10}  PATH",
+             "NAME", "TYPE", "BRIGHTNESS", "ACTUAL", "MAX", "SCALE", "OUTPUT")?;
+    for bl in backlights {
+        // Not every driver exposes every attribute, so show what we can
+        let show = |v: Result<u32>| v.map(|v| v.to_string()).unwrap_or_else(|_| "-".to_string());
+        writeln!(out, "{:<20} {:<10} {:>10} {:>10} {:>10} {:<10} {:<10}  {}",
+                 bl.sysname,
+                 bl.get_type().unwrap_or_else(|_| "-".to_string()),
+                 show(bl.get_brightness()),
+                 show(bl.get_actual_brightness()),
+                 show(bl.get_max_brightness()),
+                 bl.get_scale().to_string(),
+                 bl.connector.as_ref().map_or("-".to_string(), |c| c.to_string()),
+                 bl.root.display())?;
+    }
+    Ok(())
+}
 
-    fn read_value(&self, property: &Path) -> Result<u32> {
-        let mut f = fs::File::open(self.root.as_path().join(property))?;
-        let mut buf = String::new();
-        f.read_to_string(&mut buf)?;
-        Ok(buf.trim().parse()?)
+fn save(backlights: Vec<Backlight>, state: &StateDir) -> Result<()> {
+    for bl in backlights {
+        state.save(&bl)?;
     }
+    Ok(())
+}
 
-    fn get_max_brightness(&self) -> Result<u32> {
-        self.read_value(Path::new("max_brightness"))
+/// The devices with a saved brightness to restore, saying which have none
+/// so they aren't counted among those restored
+fn with_saved(backlights: Vec<Backlight>, state: &StateDir) -> Vec<Backlight> {
+    backlights.into_iter().filter(|bl| match state.load(bl) {
+        Ok(None) => {
+            eprintln!("No saved brightness for {}", bl.sysname);
+            false
+        }
+        // Reported when restoring
+        _ => true,
+    }).collect()
+}
+
+fn state_dir(matches: &ArgMatches) -> Result<StateDir> {
+    match matches.value_of_os("state-dir").map(PathBuf::from).or_else(StateDir::default_path) {
+        Some(path) => Ok(StateDir::new(path)),
+        None => bail!("No state directory; set --state-dir, $XDG_STATE_HOME or $HOME"),
     }
+}
 
-    fn get_brightness(&self) -> Result<u32> {
-        self.read_value(Path::new("brightness"))
+fn selector(matches: &ArgMatches, config: &Config) -> Result<Selector> {
+    let values = |name| matches.values_of(name)
+        .map(|v| v.map(String::from).collect())
+        .unwrap_or_default();
+    let mut properties = Vec::new();
+    for m in matches.values_of("match").into_iter().flatten() {
+        match m.find('=') {
+            Some(i) => properties.push((m[..i].to_string(), m[i + 1..].to_string())),
+            None => bail!(ErrorKind::InvalidValue(format!("Invalid match '{}', expected KEY=VALUE", m))),
+        }
     }
+    Ok(Selector {
+        class: Some(class(matches)?),
+        devices: matches.values_of("device").into_iter().flatten()
+            .map(|d| config.alias(d).unwrap_or(d).to_string())
+            .collect(),
+        properties,
+        drivers: values("driver"),
+        outputs: values("output"),
+    })
+}
 
-    fn set_brightness(&self, brightness: u32) -> Result<()> {
-        let mut f = fs::OpenOptions::new()
-            .write(true)
-            .open(self.root.as_path().join("brightness"))?;
-        f.write_all(&brightness.to_string().into_bytes())?;
-        Ok(())
+fn class(matches: &ArgMatches) -> Result<Class> {
+    Class::parse(matches.value_of("class").unwrap())
+}
+
+/// The curve from the command line, falling back to the config for flags
+/// that weren't given
+fn curve_policy(matches: &ArgMatches, config: &Config) -> Result<CurvePolicy> {
+    let mut overrides = Vec::new();
+    for o in matches.values_of("scale-override").into_iter().flatten() {
+        match o.find('=') {
+            Some(i) => overrides.push((o[..i].to_string(), Scale::parse(&o[i + 1..])?)),
+            None => bail!(ErrorKind::InvalidValue(format!("Invalid scale override '{}', expected NAME=SCALE", o))),
+        }
+    }
+    let curve = if given(matches, "curve") { matches.value_of("curve") } else { None };
+    let gamma = if given(matches, "gamma") { Some(parse_arg(matches, "gamma")?) } else { None };
+    config.curve_policy(curve, gamma, overrides)
+}
+
+fn floor(matches: &ArgMatches, config: &Config) -> Result<Limit> {
+    if matches.is_present("allow-off") {
+        return Ok(Limit::new(Level::Raw(0)));
     }
+    let given: Vec<&str> = matches.values_of("floor").into_iter().flatten().collect();
+    config.floor(class(matches)?, &given)
 }
 
-struct Backlights {
-    iter: udev::Devices,
+fn ceiling(matches: &ArgMatches, config: &Config) -> Result<Limit> {
+    let given: Vec<&str> = matches.values_of("ceiling").into_iter().flatten().collect();
+    config.ceiling(&given)
 }
 
-impl Backlights {
-    fn new() -> Result<Self> {
-        let context = udev::Context::new()?;
-        let mut enumerator = udev::Enumerator::new(&context)?;
-        enumerator.match_is_initialized()?;
-        enumerator.match_subsystem("backlight")?;
-        let devs = enumerator.scan_devices()?;
-        Ok(Backlights { iter: devs })
+/// The fade from the command line, or the config's when --fade isn't given
+fn fade(matches: &ArgMatches, config: &Config) -> Result<Option<Fade>> {
+    let duration = match matches.value_of("fade") {
+        Some(duration) => Some(fade::parse_duration(duration)?),
+        None => config.fade,
+    };
+    match duration {
+        Some(duration) => Ok(Some(Fade {
+            duration,
+            rate: parse_arg(matches, "fade-rate")?,
+            easing: matches.value_of("easing").unwrap().parse()?,
+        })),
+        None => Ok(None),
     }
 }
 
-impl Iterator for Backlights {
-    type Item = Backlight;
+/// VALUE, or the configured step when inc or dec is given none
+fn step(matches: &ArgMatches, config: &Config) -> Result<String> {
+    match (matches.value_of("VALUE"), &config.step) {
+        (Some(value), _) => Ok(value.to_string()),
+        (None, Some(step)) => Ok(step.clone()),
+        (None, None) => bail!(ErrorKind::InvalidValue("No VALUE given and no step set in the configuration".to_string())),
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.iter.next() {
-            Some(dev) => Some(Backlight::new(dev.syspath())),
-            _ => None,
+/// The files named by --config, or the system-wide and user configuration
+fn config_paths(matches: &ArgMatches) -> Result<Vec<PathBuf>> {
+    match matches.value_of_os("config").map(PathBuf::from) {
+        Some(ref path) if !path.exists() => {
+            bail!(ErrorKind::InvalidValue(format!("No configuration file at {}", path.display())))
         }
+        Some(path) => Ok(vec![path]),
+        None => Ok(Config::default_paths()),
     }
 }
 
-struct Update {
-    relative: bool,
-    percent: bool,
-    value: i32,
+/// Reads each config file on its own, printing every problem with it and
+/// every setting that names a device that can't be found
+fn check_config(matches: &ArgMatches, out: &mut dyn Write) -> Result<()> {
+    let paths = config_paths(matches)?;
+    let present: Vec<&PathBuf> = paths.iter().filter(|p| p.exists()).collect();
+    if present.is_empty() {
+        let looked: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        writeln!(out, "No configuration found; looked for {}", looked.join(" and "))?;
+        return Ok(());
+    }
+
+    let backlights: Vec<Backlight> = Backlights::new(&source(matches), Class::ALL)?.collect();
+    let mut errors = 0;
+    for path in present {
+        let (config, problems) = match Config::read(path) {
+            Ok(read) => read,
+            Err(Error(ErrorKind::InvalidConfig(_, message), _)) => (Config::default(), vec![message]),
+            Err(e) => return Err(e),
+        };
+        for problem in &problems {
+            writeln!(out, "{}: error: {}", path.display(), problem)?;
+        }
+        for warning in config.unmatched(&backlights) {
+            writeln!(out, "{}: warning: {}", path.display(), warning)?;
+        }
+        if problems.is_empty() {
+            writeln!(out, "{}: ok", path.display())?;
+        }
+        errors += problems.len();
+    }
+    if errors > 0 {
+        bail!(ErrorKind::InvalidValue(format!("Found {} error{} in the configuration",
+                                              errors, if errors == 1 { "" } else { "s" })));
+    }
+    Ok(())
 }
 
-impl Update {
-    fn set(valstr: &str) -> Result<Self> {
-        Update::new(false, valstr)
+fn verify(matches: &ArgMatches) -> Result<Option<Verify>> {
+    if !matches.is_present("verify") {
+        return Ok(None);
     }
-    fn inc(valstr: &str) -> Result<Self> {
-        Update::new(true, valstr)
+    Ok(Some(Verify {
+        timeout: fade::parse_duration(matches.value_of("verify-timeout").unwrap())?,
+        retries: parse_arg(matches, "verify-retries")?,
+    }))
+}
+
+fn source(matches: &ArgMatches) -> Source {
+    match matches.value_of_os("sysfs-root") {
+        Some(root) => Source::Sysfs(PathBuf::from(root)),
+        None => Source::Udev,
     }
-    fn dec(valstr: &str) -> Result<Self> {
-        let mut res = Update::new(true, valstr)?;
-        res.value *= -1;
-        Ok(res)
+}
+
+fn backend(matches: &ArgMatches) -> Backend {
+    match matches.value_of("backend").unwrap() {
+        "sysfs" => Backend::Sysfs,
+        "logind" => Backend::Logind,
+        _ => Backend::Auto,
     }
-    fn new(relative: bool, valstr: &str) -> Result<Self> {
-        Ok(Update { relative: relative, percent: valstr.contains('%'),  value: valstr.trim().trim_end_matches('%').parse()? })
+}
+
+fn subcommand<'a>(matches: &'a ArgMatches<'a>) -> (&'a str, &'a ArgMatches<'a>) {
+    match matches.subcommand() {
+        (name, Some(sub)) => (name, sub),
+        _ => unreachable!("clap requires a subcommand"),
     }
+}
 
-    fn apply(&self, backlight: Backlight) -> Result<Backlight> {
-        let max = backlight.get_max_brightness()? as i32;
-        let mut value = self.value;
+fn run(matches: &ArgMatches) -> Result<()> {
+    let (cmdstr, sub) = subcommand(matches);
+    let socket = sub.value_of_os("socket").map(PathBuf::from).unwrap_or_else(daemon::default_socket_path);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
 
-        // Step 1: Percent to brightness-units
-        if self.percent {
-            value = max * value / 100;
+    if FORWARDED.contains(&cmdstr) && !sub.is_present("no-daemon") {
+        if let Some(client) = daemon::Client::connect(&socket) {
+            let args: Option<Vec<String>> = env::args_os().skip(1).map(|a| a.into_string().ok()).collect();
+            if let Some(request) = args.and_then(daemon::encode_request) {
+                return client.forward(&request, &mut out);
+            }
         }
+    }
+
+    if cmdstr == "config" {
+        return check_config(sub, &mut out);
+    }
+    let config = Config::load(&config_paths(sub)?)?;
+
+    let source = source(sub);
+    let backend = backend(sub);
+    // The daemon serves every class, since requests pick theirs, and presets
+    // cover screens and keyboards alike
+    let classes = match cmdstr {
+        "daemon" | "preset" => Class::ALL.to_vec(),
+        _ => vec![class(sub)?],
+    };
+    // Watching starts before enumerating, so devices that turn up in
+    // between aren't missed
+    let watcher = match cmdstr {
+        "daemon" if !sub.is_present("no-hotplug") => Some(hotplug::Watcher::new(&source, &classes)?),
+        _ => None,
+    };
+    let available: Vec<Backlight> = Backlights::new(&source, &classes)?
+        .filter(|bl| !config.is_ignored(bl))
+        .map(|bl| Backlight { backend, ..bl })
+        .collect();
 
-        // Step 2: Relative to absolute
-        if self.relative {
-            let original = backlight.get_brightness()? as i32;
-            value += original;
+    if cmdstr == "daemon" {
+        return run_daemon(sub, &source, &socket, watcher, available, config);
+    }
+
+    execute(cmdstr, sub, available, &config, &mut out)
+}
+
+/// The ambient light sensor settings for `daemon --auto`, from the command
+/// line, then the config, then the defaults, with the curve learned so far
+fn auto(sub: &ArgMatches, source: &Source, config: &Config) -> Result<Auto> {
+    let duration = |name, configured: Option<Duration>, default| -> Result<Duration> {
+        match sub.value_of(name) {
+            Some(duration) => fade::parse_duration(duration),
+            None => Ok(configured.unwrap_or(default)),
+        }
+    };
+    let number = |name, configured: Option<f64>, default| -> Result<f64> {
+        match sub.value_of(name) {
+            Some(_) => parse_arg(sub, name),
+            None => Ok(configured.unwrap_or(default)),
         }
+    };
 
-        // Step 3: Clamp to min/max
-        if value > max {
-            value = max;
+    let sensor = match sub.value_of_os("sensor").map(PathBuf::from).or_else(|| config.auto.sensor.clone()) {
+        Some(path) => Sensor::new(path),
+        None => Sensor::find(&match *source {
+            Source::Sysfs(ref root) => root.clone(),
+            Source::Udev => PathBuf::from("/sys"),
+        })?,
+    };
+    // Better to refuse to start than to sit there never adjusting anything
+    sensor.read_lux()?;
+    let base = match sub.value_of("auto-curve") {
+        Some(curve) => LuxCurve::parse(curve)?,
+        None => config.auto.curve.clone().unwrap_or_default(),
+    };
+    // Without a state directory there's nowhere to keep what's learned, but
+    // that's no reason not to follow the sensor
+    let store = state_dir(sub).ok().map(|state| state.file("auto-curve"));
+    let curve = match store {
+        Some(ref path) => Learned::load(base, path)?,
+        None => Learned::new(base),
+    };
+    let smoothing = als::smoothing(number("auto-smoothing", config.auto.smoothing, 0.3)?)?;
+    let hysteresis = als::hysteresis(number("auto-hysteresis", config.auto.hysteresis, 5.0)?)?;
+    let interval = duration("auto-interval", config.auto.interval, Duration::from_secs(1))?;
+    Ok(Auto::new(sensor, curve, Smoother::new(smoothing, hysteresis), interval, store))
+}
+
+fn run_daemon(sub: &ArgMatches, source: &Source, socket: &Path, watcher: Option<hotplug::Watcher>,
+              available: Vec<Backlight>, config: Config) -> Result<()> {
+    let mut wanted = selector(sub, &config)?;
+    wanted.class = None;
+    // Devices may still turn up later when we're watching for them
+    let owned = if watcher.is_some() {
+        available.into_iter().filter(|bl| wanted.matches(bl)).collect()
+    } else {
+        wanted.select(available)?
+    };
+    let owned = Arc::new(Mutex::new(owned));
+    // Percentage along the curve that the last update left the first device
+    // at, per subsystem so a keyboard doesn't inherit the screen's level
+    let last_percent: Arc<Mutex<HashMap<String, f64>>> = Arc::new(Mutex::new(HashMap::new()));
+
+    if let Some(watcher) = watcher {
+        let owned = owned.clone();
+        let last_percent = last_percent.clone();
+        let curve = curve_policy(sub, &config)?;
+        let ceiling = ceiling(sub, &config)?;
+        let config = config.clone();
+        let reapply = sub.is_present("reapply");
+        let all = sub.is_present("all");
+        let backend = backend(sub);
+        thread::spawn(move || {
+            let result = watcher.run(|event| match event {
+                hotplug::Event::Added(bl) => {
+                    if !wanted.matches(&bl) || config.is_ignored(&bl) {
+                        return;
+                    }
+                    let bl = Backlight { backend, ..bl };
+                    let mut owned = owned.lock().unwrap();
+                    owned.retain(|b| b.root != bl.root);
+                    owned.push(bl.clone());
+                    // Only a device requests would reach, so a late second
+                    // interface to a panel doesn't get written too
+                    let reached = all || select::primary(owned.clone()).iter().any(|b| b.root == bl.root);
+                    drop(owned);
+                    let percent = last_percent.lock().unwrap().get(bl.subsystem()).cloned();
+                    if let (true, true, Some(percent)) = (reapply, reached, percent) {
+                        let result = Update::set(&format!("{}%", percent))
+                            .and_then(|update| update.with_curve(curve.clone()).with_ceiling(ceiling.clone()).apply(bl.clone()));
+                        if let Err(e) = result {
+                            eprintln!("Failed to restore brightness of {}: {}", bl.sysname, e);
+                        }
+                    }
+                }
+                hotplug::Event::Removed(root) => owned.lock().unwrap().retain(|b| b.root != root),
+            });
+            if let Err(e) = result {
+                eprintln!("Stopped watching for new backlights: {}", e);
+            }
+        });
+    }
+
+    let auto = if sub.is_present("auto") {
+        Some(Arc::new(Mutex::new(auto(sub, source, &config)?)))
+    } else {
+        None
+    };
+    if let Some(ref auto) = auto {
+        let auto = auto.clone();
+        let fade = Fade {
+            duration: match sub.value_of("auto-fade") {
+                Some(duration) => fade::parse_duration(duration)?,
+                None => config.auto.fade.unwrap_or(Duration::from_secs(1)),
+            },
+            // As --fade-rate defaults to
+            rate: 60,
+            easing: fade::Easing::Exponential,
+        };
+        let owned = owned.clone();
+        let last_percent = last_percent.clone();
+        let curve = curve_policy(sub, &config)?;
+        let (floor, ceiling) = (floor(sub, &config)?, ceiling(sub, &config)?);
+        let all = sub.is_present("all");
+        thread::spawn(move || {
+            Auto::run(&auto, |percent, cancelled| {
+                let mut screens: Vec<Backlight> = owned.lock().unwrap().iter()
+                    .filter(|bl| bl.subsystem() == Class::Backlight.subsystem())
+                    .cloned()
+                    .collect();
+                if !all {
+                    screens = select::primary(screens);
+                }
+                let update = match Update::set(&format!("{}%", percent.round())) {
+                    Ok(update) => update.with_curve(curve.clone()).with_ceiling(ceiling.clone()),
+                    Err(e) => {
+                        eprintln!("Failed to follow the ambient light sensor: {}", e);
+                        return;
+                    }
+                };
+                let mut targets = Vec::new();
+                for bl in screens {
+                    // Unlike set, which may turn a screen off on purpose,
+                    // the sensor never goes below the floor
+                    let min = bl.get_max_brightness().map(|max| floor.for_device(&bl).to_raw(curve.resolve(&bl), max));
+                    match update.target(&bl).and_then(|target| Ok(target.max(min?))) {
+                        Ok(target) => targets.push((bl, target)),
+                        Err(e) => eprintln!("Failed to adjust {}: {}", bl.sysname, e),
+                    }
+                }
+                for ((bl, _), result) in targets.iter().zip(fade.run_until(&targets, cancelled)) {
+                    if let Err(e) = result {
+                        eprintln!("Failed to adjust {}: {}", bl.sysname, e);
+                    }
+                }
+                if !cancelled() {
+                    last_percent.lock().unwrap().insert(Class::Backlight.subsystem().to_string(), percent);
+                }
+            }, |e| eprintln!("Failed to read the ambient light sensor: {}", e))
+        });
+    }
+
+    let started = sub;
+    daemon::serve(socket, |args, out| {
+        let program = iter::once("backctl".to_string());
+        let matches = app().get_matches_from_safe(program.chain(args.iter().cloned()))
+            .map_err(|e| Error::from(ErrorKind::InvalidValue(e.message)))?;
+        let (cmdstr, sub) = subcommand(&matches);
+        if !FORWARDED.contains(&cmdstr) {
+            bail!("The daemon doesn't run {}", cmdstr);
+        }
+        check_request(started, sub)?;
+        let devices = owned.lock().unwrap().clone();
+        let mut selected = selector(sub, &config)?.select(devices.clone())?;
+        if !sub.is_present("all") {
+            selected = select::primary(selected);
+        }
+        let first = selected.into_iter().next();
+        // Held so the sensor can't fade over a change made by hand
+        let mut following = match auto {
+            Some(ref auto) if cmdstr != "get" => Some(Auto::manual(auto)),
+            _ => None,
+        };
+        execute(cmdstr, sub, devices, &config, out)?;
+
+        if let (true, Some(bl)) = (cmdstr != "get", first) {
+            let curve = curve_policy(sub, &config)?.resolve(&bl);
+            let (brightness, max) = (bl.get_brightness()?, bl.get_max_brightness()?);
+            let percent = curve.to_percent(brightness, max);
+            last_percent.lock().unwrap().insert(bl.subsystem().to_string(), percent);
+            // A screen set by hand teaches the sensor curve what the user
+            // wants in this light, unless it was turned off or dimmed past
+            // the floor, which says nothing about the light
+            let min = config.floor(Class::Backlight, &[])?.for_device(&bl).to_raw(curve, max);
+            let learn = bl.subsystem() == Class::Backlight.subsystem() && brightness > 0 && brightness >= min;
+            if let (Some(ref mut auto), true) = (&mut following, learn) {
+                if let Err(e) = auto.adjusted(percent) {
+                    eprintln!("Failed to save the learned brightness: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }, |e| eprintln!("Failed to answer request: {}", e))
+}
+
+/// Runs a command against the `available` backlights
+fn execute(cmdstr: &str, sub: &ArgMatches, available: Vec<Backlight>, config: &Config,
+           out: &mut dyn Write) -> Result<()> {
+    if cmdstr == "preset" {
+        return preset(sub, available, config, out);
+    }
+    let mut backlights = selector(sub, config)?.select(available)?;
+    // list shows everything, so the user can see what the policy chose
+    // from, and save and restore keep every device as it was
+    if !["list", "save", "restore"].contains(&cmdstr) && !sub.is_present("all") {
+        backlights = select::primary(backlights);
+    }
+    let curve = curve_policy(sub, config)?;
+    let format: Format = sub.value_of("format").unwrap().parse()?;
+    let update = match cmdstr {
+        "get" => return get(backlights, &curve, sub.is_present("actual"), sub.is_present("raw"),
+                            sub.is_present("percent"), format, out),
+        "list" => return list(backlights, &curve, format, out),
+        "watch" => {
+            let watch = Watch {
+                interval: fade::parse_duration(sub.value_of("interval").unwrap())?,
+                raw: sub.is_present("raw"),
+                percent: sub.is_present("percent"),
+                format: if sub.is_present("json") { Format::Json } else { format },
+            };
+            return watch.run(&backlights, &source(sub), &curve, out);
         }
-        if value < 0 {
-            value = 0;
+        "save" => return save(backlights, &state_dir(sub)?),
+        "restore" => {
+            let (state, floor) = (state_dir(sub)?, floor(sub, config)?);
+            // A panel saved dark comes back no dimmer than the floor
+            return batch::write_each(with_saved(backlights, &state), sub.is_present("strict"),
+                                     |bl| state.restore(bl, &curve, &floor).map(|_| ()), batch::put_back)
+                .report(&curve, format, out);
         }
+        "off" | "on" | "toggle" => {
+            let state = state_dir(sub)?.subdir("power");
+            let write: PowerFn = match cmdstr {
+                "off" => power::off,
+                "on" => power::on,
+                _ => power::toggle,
+            };
+            // Under --strict each device is put back on or off as it was
+            let was_on: Vec<(PathBuf, Option<bool>)> = backlights.iter()
+                .map(|bl| (bl.root.clone(), power::is_on(bl).ok()))
+                .collect();
+            let undo = |bl: &Backlight, _: Option<u32>| {
+                match was_on.iter().find(|(root, _)| *root == bl.root).and_then(|&(_, on)| on) {
+                    Some(true) => power::on(bl, &state),
+                    Some(false) => power::off(bl, &state),
+                    None => bail!("Whether {} was on before the change is unknown", bl.sysname),
+                }
+            };
+            return batch::write_each(backlights, sub.is_present("strict"), |bl| write(bl, &state), undo)
+                .report(&curve, format, out);
+        }
+        "inc" => Update::inc(&step(sub, config)?)?,
+        "dec" => Update::dec(&step(sub, config)?)?,
+        "set" => Update::set(sub.value_of("VALUE").unwrap())?,
+        _ => unreachable!("Unknown subcommand {}", cmdstr),
+    }.with_curve(curve.clone())
+        .with_floor(floor(sub, config)?)
+        .with_ceiling(ceiling(sub, config)?)
+        .with_verify(verify(sub)?);
+    let updates = backlights.into_iter().map(|bl| (bl, update.clone())).collect();
+    batch::apply(updates, fade(sub, config)?.as_ref(), sub.is_present("strict")).report(&curve, format, out)
+}
 
-        backlight.set_brightness(value as u32)
-            .and_then(|()| Ok(backlight))
+/// The devices a preset may touch: any class unless --class is given
+fn preset_selector(matches: &ArgMatches, config: &Config) -> Result<Selector> {
+    let mut wanted = selector(matches, config)?;
+    if !given(matches, "class") {
+        wanted.class = None;
     }
+    Ok(wanted)
 }
 
-fn main() {
-    let matches = App::new("Backlight Control")
+/// Applies a preset from the config, or lists or saves them
+fn preset(sub: &ArgMatches, available: Vec<Backlight>, config: &Config, out: &mut dyn Write) -> Result<()> {
+    let format: Format = sub.value_of("format").unwrap().parse()?;
+    match sub.subcommand() {
+        ("list", _) => return output::write_presets(format, &config.presets, out),
+        ("save", Some(save)) => return save_preset(save, available, config),
+        _ => {}
+    }
+
+    let wanted = preset_selector(sub, config)?;
+    let curve = curve_policy(sub, config)?;
+    let ceiling = ceiling(sub, config)?;
+    let verify = verify(sub)?;
+    let updates = config.preset_updates(sub.value_of("NAME").unwrap(), &available, &wanted, |level| {
+        Ok(Update::set(level)?.with_curve(curve.clone()).with_ceiling(ceiling.clone()).with_verify(verify))
+    })?;
+    batch::apply(updates, fade(sub, config)?.as_ref(), sub.is_present("strict")).report(&curve, format, out)
+}
+
+/// Saves the raw brightness of the selected devices as a preset in the
+/// user's configuration, or the file given by --config
+fn save_preset(save: &ArgMatches, available: Vec<Backlight>, config: &Config) -> Result<()> {
+    let mut backlights = preset_selector(save, config)?.select(available)?;
+    if !save.is_present("all") {
+        backlights = select::primary(backlights);
+    }
+    let levels = config.preset_levels(&backlights)?;
+    let path = match save.value_of_os("config").map(PathBuf::from).or_else(Config::user_path) {
+        Some(path) => path,
+        None => bail!("No configuration directory; set --config, $XDG_CONFIG_HOME or $HOME"),
+    };
+    config::save_preset(&path, save.value_of("NAME").unwrap(), &levels)
+}
+
+/// A power command
+type PowerFn = fn(&Backlight, &StateDir) -> Result<()>;
+
+fn app() -> App<'static, 'static> {
+    let value = || Arg::with_name("VALUE")
+        .required(true)
+        .help("Raw brightness, or a percentage of the maximum when suffixed with %");
+    let step = || Arg::with_name("VALUE")
+        .help("Raw brightness, or a percentage of the maximum when suffixed with % \
+               [default: step from the configuration]");
+    let fade_args = || vec![
+        Arg::with_name("fade")
+            .long("fade")
+            .short("f")
+            .value_name("DURATION")
+            .takes_value(true)
+            .help("Transition gradually over DURATION (e.g. 400ms, 1.5s); 0 disables a configured fade"),
+        Arg::with_name("fade-rate")
+            .long("fade-rate")
+            .value_name("FPS")
+            .takes_value(true)
+            .default_value("60")
+            .help("Frames per second written during a fade"),
+        Arg::with_name("easing")
+            .long("easing")
+            .takes_value(true)
+            .possible_values(&["linear", "ease-in-out", "exponential"])
+            .default_value("linear")
+            .help("How brightness is spread over a fade"),
+    ];
+    let strict_arg = || Arg::with_name("strict")
+        .long("strict")
+        .help("If any device fails, put the others back to their previous brightness");
+    let verify_args = || vec![
+        Arg::with_name("verify")
+            .long("verify")
+            .help("Fail unless actual_brightness reaches the new value"),
+        Arg::with_name("verify-timeout")
+            .long("verify-timeout")
+            .value_name("DURATION")
+            .takes_value(true)
+            .default_value("200ms")
+            .help("How long --verify waits after each write"),
+        Arg::with_name("verify-retries")
+            .long("verify-retries")
+            .value_name("N")
+            .takes_value(true)
+            .default_value("2")
+            .help("How many times --verify writes the value again before giving up"),
+    ];
+    let floor_arg = || Arg::with_name("floor")
+        .long("floor")
+        .value_name("[NAME=]LEVEL")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("Never dim below LEVEL (raw or %), for every device or those matching NAME [default: 1]");
+    let ceiling_arg = || Arg::with_name("ceiling")
+        .long("ceiling")
+        .value_name("[NAME=]LEVEL")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("Never brighten past LEVEL (raw or %), for every device or those matching NAME");
+    let floor_args = || vec![
+        floor_arg(),
+        Arg::with_name("allow-off")
+            .long("allow-off")
+            .conflicts_with("floor")
+            .help("Let the brightness reach zero, turning the backlight off"),
+    ];
+    App::new("Backlight Control")
         .author("Kevin Cuzner <kevin@kevincuzner.com>")
         .about("Sets the backlight brightness through sysfs")
-        .arg(Arg::with_name("CMD")
-             .required(true)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("device")
+             .long("device")
+             .short("d")
+             .value_name("NAME")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .global(true)
+             .help("Only use backlights whose sysname matches NAME (globs allowed)"))
+        .arg(Arg::with_name("match")
+             .long("match")
+             .short("m")
+             .value_name("KEY=VALUE")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .global(true)
+             .help("Only use backlights whose udev property KEY matches VALUE (globs allowed)"))
+        .arg(Arg::with_name("driver")
+             .long("driver")
+             .value_name("NAME")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .global(true)
+             .help("Only use backlights whose parent driver matches NAME (globs allowed)"))
+        .arg(Arg::with_name("output")
+             .long("output")
+             .short("o")
+             .value_name("CONNECTOR")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .global(true)
+             .help("Only use backlights for the DRM connector CONNECTOR, e.g. eDP-1 or card1-DP-2 (globs allowed)"))
+        .arg(Arg::with_name("all")
+             .long("all")
+             .global(true)
+             .help("Use every selected device, instead of only the preferred one for each panel"))
+        .arg(Arg::with_name("sysfs-root")
+             .long("sysfs-root")
+             .value_name("DIR")
+             .takes_value(true)
+             .global(true)
+             .help("Read devices from DIR/class/<class> instead of enumerating them through udev"))
+        .arg(Arg::with_name("class")
+             .long("class")
              .takes_value(true)
-             .possible_value("inc")
-             .possible_value("dec")
-             .possible_value("set"))
-        .arg(Arg::with_name("VALUE")
-             .required(true))
-        .get_matches();
-
-    let cmdstr = matches.value_of("CMD").expect("No command supplied");
-    let valstr = matches.value_of("VALUE").expect("No value supplied");
-
-    let update = match cmdstr.as_ref() {
-        "inc" => Update::inc(&valstr).expect("Unable to create increment update"),
-        "dec" => Update::dec(&valstr).expect("Unable to create decrement update"),
-        "set" => Update::set(&valstr).expect("Unable to create set update"),
-        _ => panic!("Invalid command supplied"),
+             .possible_values(&["backlight", "leds"])
+             .default_value("backlight")
+             .global(true)
+             .help("Which devices to control: screen backlights, or LEDs such as keyboard backlights"))
+        .arg(Arg::with_name("curve")
+             .long("curve")
+             .takes_value(true)
+             .possible_values(&["auto", "linear", "gamma", "log"])
+             .default_value("auto")
+             .global(true)
+             .help("How percentages map to raw brightness; gamma and log spread steps evenly to the eye, \
+                    auto uses gamma only for devices whose kernel scale is linear"))
+        .arg(Arg::with_name("gamma")
+             .long("gamma")
+             .value_name("EXPONENT")
+             .takes_value(true)
+             .default_value("2.2")
+             .global(true)
+             .help("Exponent used by --curve gamma and --curve auto"))
+        .arg(Arg::with_name("scale-override")
+             .long("scale-override")
+             .value_name("NAME=SCALE")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .global(true)
+             .help("Treat backlights matching NAME as having SCALE (linear, non-linear or unknown) \
+                    for --curve auto"))
+        .arg(Arg::with_name("backend")
+             .long("backend")
+             .takes_value(true)
+             .possible_values(&["auto", "sysfs", "logind"])
+             .default_value("auto")
+             .global(true)
+             .help("How to write brightness; auto asks systemd-logind when sysfs denies permission"))
+        .arg(Arg::with_name("format")
+             .long("format")
+             .takes_value(true)
+             .possible_values(&["text", "json", "env"])
+             .default_value("text")
+             .global(true)
+             .help("How to print results: text, a JSON array, or BACKCTL_<DEV>_<FIELD>= lines for eval. \
+                    Commands that change brightness report each device's old and new value"))
+        .arg(Arg::with_name("config")
+             .long("config")
+             .value_name("FILE")
+             .takes_value(true)
+             .global(true)
+             .help("Read settings from FILE only [default: /etc/backctl/config.toml, then \
+                    $XDG_CONFIG_HOME/backctl/config.toml]"))
+        .arg(Arg::with_name("state-dir")
+             .long("state-dir")
+             .value_name("DIR")
+             .takes_value(true)
+             .global(true)
+             .help("Where save, restore and off keep brightness, and daemon --auto what it learns [default: $XDG_STATE_HOME/backctl]"))
+        .arg(Arg::with_name("socket")
+             .long("socket")
+             .value_name("PATH")
+             .takes_value(true)
+             .global(true)
+             .help("Daemon socket [default: $XDG_RUNTIME_DIR/backctl.sock]"))
+        .arg(Arg::with_name("no-daemon")
+             .long("no-daemon")
+             .global(true)
+             .help("Write sysfs directly even when a daemon is running"))
+        .subcommand(SubCommand::with_name("inc")
+                    .about("Increases the brightness")
+                    .arg(step())
+                    .args(&fade_args())
+                    .args(&verify_args())
+                    .arg(strict_arg())
+                    .arg(ceiling_arg())
+                    .args(&floor_args()))
+        .subcommand(SubCommand::with_name("dec")
+                    .about("Decreases the brightness")
+                    .arg(step())
+                    .args(&fade_args())
+                    .args(&verify_args())
+                    .arg(strict_arg())
+                    .arg(ceiling_arg())
+                    .args(&floor_args()))
+        .subcommand(SubCommand::with_name("set")
+                    .about("Sets the brightness")
+                    .arg(value())
+                    .args(&fade_args())
+                    .args(&verify_args())
+                    .arg(strict_arg())
+                    .arg(ceiling_arg()))
+        .subcommand(SubCommand::with_name("get")
+                    .about("Prints the brightness, prefixed by the device name when several match")
+                    .arg(Arg::with_name("actual")
+                         .long("actual")
+                         .short("a")
+                         .help("Report actual_brightness, what the hardware is showing, \
+                                rather than the last value written"))
+                    .arg(Arg::with_name("raw")
+                         .long("raw")
+                         .short("r")
+                         .help("Print the raw brightness value"))
+                    .arg(Arg::with_name("percent")
+                         .long("percent")
+                         .short("p")
+                         .help("Print the brightness as a percentage of the maximum")))
+        .subcommand(SubCommand::with_name("list")
+                    .about("Lists backlight devices and their current state"))
+        .subcommand(SubCommand::with_name("watch")
+                    .about("Prints the brightness, then a line whenever it changes")
+                    .arg(Arg::with_name("raw")
+                         .long("raw")
+                         .short("r")
+                         .help("Print the raw brightness value"))
+                    .arg(Arg::with_name("percent")
+                         .long("percent")
+                         .short("p")
+                         .help("Print the brightness as a percentage of the maximum"))
+                    .arg(Arg::with_name("json")
+                         .long("json")
+                         .conflicts_with_all(&["raw", "percent"])
+                         .help("Print each change as a JSON object, like --format json"))
+                    .arg(Arg::with_name("interval")
+                         .long("interval")
+                         .value_name("DURATION")
+                         .takes_value(true)
+                         .default_value("1s")
+                         .help("How often to re-read devices that change without a udev event")))
+        .subcommand(SubCommand::with_name("save")
+                    .about("Saves the brightness of each device to the state directory"))
+        .subcommand(SubCommand::with_name("restore")
+                    .about("Restores the brightness saved by save")
+                    .arg(floor_arg())
+                    .arg(strict_arg()))
+        .subcommand(SubCommand::with_name("off")
+                    .about("Powers the backlight down through bl_power, remembering its brightness")
+                    .arg(strict_arg()))
+        .subcommand(SubCommand::with_name("on")
+                    .about("Powers the backlight back up at the brightness it had")
+                    .arg(strict_arg()))
+        .subcommand(SubCommand::with_name("toggle")
+                    .about("Powers the backlight down if it is on, otherwise back up")
+                    .arg(strict_arg()))
+        .subcommand(SubCommand::with_name("preset")
+                    .about("Sets devices to the levels of a preset from the configuration")
+                    .setting(AppSettings::SubcommandsNegateReqs)
+                    .arg(Arg::with_name("NAME")
+                         .required(true)
+                         .help("The preset to apply"))
+                    .args(&fade_args())
+                    .args(&verify_args())
+                    .arg(strict_arg())
+                    .arg(ceiling_arg())
+                    .subcommand(SubCommand::with_name("list")
+                                .about("Lists the presets and the level each sets"))
+                    .subcommand(SubCommand::with_name("save")
+                                .about("Saves the current brightness of each device as a preset \
+                                        in the user's configuration")
+                                .arg(Arg::with_name("NAME")
+                                     .required(true)
+                                     .help("The preset to save, replacing any of the same name"))))
+        .subcommand(SubCommand::with_name("config")
+                    .about("Works with the configuration file")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("check")
+                                .about("Reports invalid settings, and settings for devices that can't be found")))
+        .subcommand(SubCommand::with_name("daemon")
+                    .about("Keeps the selected devices open and serves inc, dec, set and get over a socket")
+                    .arg(Arg::with_name("no-hotplug")
+                         .long("no-hotplug")
+                         .help("Only serve the devices present at startup"))
+                    .arg(Arg::with_name("reapply")
+                         .long("reapply")
+                         .conflicts_with("no-hotplug")
+                         .help("Set devices that appear later to the last requested brightness"))
+                    .arg(Arg::with_name("auto")
+                         .long("auto")
+                         .help("Follow the ambient light sensor, fading screens to the level it calls for"))
+                    .arg(Arg::with_name("sensor")
+                         .long("sensor")
+                         .value_name("DIR")
+                         .takes_value(true)
+                         .requires("auto")
+                         .help("The IIO device to read [default: the first with in_illuminance_raw]"))
+                    .arg(Arg::with_name("auto-curve")
+                         .long("auto-curve")
+                         .value_name("LUX:PERCENT,...")
+                         .takes_value(true)
+                         .requires("auto")
+                         .help("The brightness for each light level, interpolated between points \
+                                [default: 0:5,10:20,100:40,1000:70,10000:100]"))
+                    .arg(Arg::with_name("auto-interval")
+                         .long("auto-interval")
+                         .value_name("DURATION")
+                         .takes_value(true)
+                         .requires("auto")
+                         .help("How often the sensor is read [default: 1s]"))
+                    .arg(Arg::with_name("auto-fade")
+                         .long("auto-fade")
+                         .value_name("DURATION")
+                         .takes_value(true)
+                         .requires("auto")
+                         .help("How long each automatic change takes [default: 1s]"))
+                    .arg(Arg::with_name("auto-smoothing")
+                         .long("auto-smoothing")
+                         .value_name("WEIGHT")
+                         .takes_value(true)
+                         .requires("auto")
+                         .help("How much each reading counts against the running average, up to 1 for \
+                                none [default: 0.3]"))
+                    .arg(Arg::with_name("auto-hysteresis")
+                         .long("auto-hysteresis")
+                         .value_name("PERCENT")
+                         .takes_value(true)
+                         .requires("auto")
+                         .help("Ignore changes in light that would move the brightness less than this \
+                                [default: 5]")))
+}
+
+fn main() {
+    let matches = match app().get_matches_from_safe(env::args_os()) {
+        Ok(matches) => matches,
+        // Help and version go to stdout and aren't failures
+        Err(ref e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            process::exit(2);
+        }
     };
 
-    for bl in Backlights::new().unwrap() {
-        update.apply(bl).unwrap();
+    if let Err(e) = run(&matches) {
+        eprintln!("{}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("caused by: {}", cause);
+        }
+        if let Some(hint) = hint(&e) {
+            eprintln!("hint: {}", hint);
+        }
+        process::exit(exit_code(&e));
     }
 }
@@ -0,0 +1,209 @@
+//! Machine-readable output, for scripts and status bars
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use backlight::Backlight;
+use config::Preset;
+use curve::CurvePolicy;
+use {Error, ErrorKind, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Whatever each command normally prints
+    Text,
+    /// A JSON array of devices, or one object per line when streaming
+    Json,
+    /// `BACKCTL_<DEV>_<FIELD>=value` lines for `eval`
+    Env,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "env" => Ok(Format::Env),
+            _ => bail!(ErrorKind::InvalidValue(format!("Unknown format '{}', expected text, json or env", s))),
+        }
+    }
+}
+
+/// What a command saw of, or did to, one device
+pub struct Report {
+    pub device: String,
+    pub syspath: PathBuf,
+    pub kind: Option<String>,
+    pub output: Option<String>,
+    /// The brightness before a command changed it
+    pub old: Option<u32>,
+    pub brightness: u32,
+    /// `actual_brightness`, for commands that show both
+    pub actual: Option<u32>,
+    pub max: u32,
+    /// Along the device's curve, rounded as `get` shows it
+    pub percent: f64,
+}
+
+impl Report {
+    /// Describes `backlight` at `brightness`
+    pub fn new(backlight: &Backlight, brightness: u32, curve: &CurvePolicy) -> Result<Self> {
+        let max = backlight.get_max_brightness()?;
+        Ok(Report {
+            device: backlight.sysname.clone(),
+            syspath: backlight.root.clone(),
+            kind: backlight.get_type().ok(),
+            output: backlight.connector.as_ref().map(|c| c.to_string()),
+            old: None,
+            brightness,
+            actual: None,
+            max,
+            percent: curve.resolve(backlight).to_percent(brightness, max).round(),
+        })
+    }
+
+    /// Describes a device a command just wrote, which was at `old` before
+    pub fn written(backlight: &Backlight, old: Option<u32>, curve: &CurvePolicy) -> Result<Self> {
+        let mut report = Report::new(backlight, backlight.get_brightness()?, curve)?;
+        report.old = old;
+        Ok(report)
+    }
+
+    /// A single JSON object, leaving out values that don't apply
+    pub fn to_json(&self) -> String {
+        let optional = |s: &Option<String>| s.as_ref().map_or("null".to_string(), |s| json_string(s));
+        let mut fields = vec![
+            format!("\"device\":{}", json_string(&self.device)),
+            format!("\"syspath\":{}", json_string(&self.syspath.to_string_lossy())),
+            format!("\"type\":{}", optional(&self.kind)),
+            format!("\"output\":{}", optional(&self.output)),
+        ];
+        if let Some(old) = self.old {
+            fields.push(format!("\"old\":{}", old));
+        }
+        fields.push(format!("\"brightness\":{}", self.brightness));
+        if let Some(actual) = self.actual {
+            fields.push(format!("\"actual\":{}", actual));
+        }
+        fields.push(format!("\"max\":{}", self.max));
+        fields.push(format!("\"percent\":{}", self.percent));
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// `BACKCTL_<DEV>_<FIELD>=value` lines, quoted for the shell
+    pub fn to_env(&self) -> Vec<String> {
+        let prefix = format!("BACKCTL_{}_", env_name(&self.device));
+        let mut vars = vec![
+            ("SYSPATH", shell_quote(&self.syspath.to_string_lossy())),
+            ("TYPE", shell_quote(self.kind.as_deref().unwrap_or_default())),
+            ("OUTPUT", shell_quote(self.output.as_deref().unwrap_or_default())),
+        ];
+        if let Some(old) = self.old {
+            vars.push(("OLD_BRIGHTNESS", old.to_string()));
+        }
+        vars.push(("BRIGHTNESS", self.brightness.to_string()));
+        if let Some(actual) = self.actual {
+            vars.push(("ACTUAL_BRIGHTNESS", actual.to_string()));
+        }
+        vars.push(("MAX_BRIGHTNESS", self.max.to_string()));
+        vars.push(("PERCENT", self.percent.to_string()));
+        vars.into_iter().map(|(name, value)| format!("{}{}={}", prefix, name, value)).collect()
+    }
+
+    /// Writes one report on its own, as a streaming command does
+    pub fn write(&self, format: Format, out: &mut dyn Write) -> Result<()> {
+        match format {
+            Format::Json => writeln!(out, "{}", self.to_json())?,
+            Format::Env => {
+                for line in self.to_env() {
+                    writeln!(out, "{}", line)?;
+                }
+            }
+            Format::Text => unreachable!("text output is up to each command"),
+        }
+        Ok(())
+    }
+}
+
+/// Writes the reports of a whole command, as a JSON array or env lines
+pub fn write(format: Format, reports: &[Report], out: &mut dyn Write) -> Result<()> {
+    match format {
+        Format::Json => {
+            let objects: Vec<String> = reports.iter().map(Report::to_json).collect();
+            writeln!(out, "[{}]", objects.join(","))?;
+        }
+        _ => {
+            for report in reports {
+                report.write(format, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes presets as a line each of `NAME DEVICE=LEVEL...`, a JSON array
+/// of objects, or `BACKCTL_PRESET_<NAME>_<DEVICE>=LEVEL` lines
+pub fn write_presets(format: Format, presets: &[Preset], out: &mut dyn Write) -> Result<()> {
+    match format {
+        Format::Text => {
+            for preset in presets {
+                let levels: Vec<String> = preset.levels.iter().map(|(device, level)| format!("{}={}", device, level)).collect();
+                writeln!(out, "{} {}", preset.name, levels.join(" "))?;
+            }
+        }
+        Format::Json => {
+            let objects: Vec<String> = presets.iter().map(|preset| {
+                let levels: Vec<String> = preset.levels.iter()
+                    .map(|(device, level)| format!("{}:{}", json_string(device), json_string(level)))
+                    .collect();
+                format!("{{\"name\":{},\"levels\":{{{}}}}}", json_string(&preset.name), levels.join(","))
+            }).collect();
+            writeln!(out, "[{}]", objects.join(","))?;
+        }
+        Format::Env => {
+            for preset in presets {
+                for (device, level) in &preset.levels {
+                    writeln!(out, "BACKCTL_PRESET_{}_{}={}", env_name(&preset.name), env_name(device), shell_quote(level))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Quotes and escapes `s` as a JSON string
+pub fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// A name as it can appear in a shell variable name: `tpacpi::kbd_backlight`
+/// becomes `TPACPI__KBD_BACKLIGHT`
+fn env_name(sysname: &str) -> String {
+    sysname.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+/// Quotes `s` for a POSIX shell, leaving plain words alone
+fn shell_quote(s: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:,+@%".contains(c);
+    if !s.is_empty() && s.chars().all(plain) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
@@ -0,0 +1,59 @@
+//! Switching backlights off and on without losing their brightness
+
+use backlight::Backlight;
+use state::StateDir;
+use Result;
+
+/// `bl_power` values, from the kernel's framebuffer blanking levels
+const FB_BLANK_UNBLANK: u32 = 0;
+const FB_BLANK_POWERDOWN: u32 = 4;
+
+/// Whether the backlight is lit. Without `bl_power` a backlight at zero
+/// counts as off.
+pub fn is_on(backlight: &Backlight) -> Result<bool> {
+    match backlight.get_power()? {
+        Some(power) => Ok(power == FB_BLANK_UNBLANK),
+        None => Ok(backlight.get_brightness()? > 0),
+    }
+}
+
+/// Powers the backlight down through `bl_power`, or by setting it to zero
+/// on drivers without it. The brightness is remembered in `state` either
+/// way, since some drivers reset it while powered down.
+pub fn off(backlight: &Backlight, state: &StateDir) -> Result<()> {
+    if !is_on(backlight)? {
+        return Ok(());
+    }
+    state.save(backlight)?;
+    match backlight.get_power()? {
+        Some(_) => backlight.set_power(FB_BLANK_POWERDOWN),
+        None => backlight.set_brightness(0),
+    }
+}
+
+/// Powers the backlight back up and, if it came back at zero, restores the
+/// brightness `off` remembered. A brightness changed while powered down is
+/// kept. With nothing remembered a dark backlight comes back at full
+/// brightness rather than staying off.
+pub fn on(backlight: &Backlight, state: &StateDir) -> Result<()> {
+    if let Some(power) = backlight.get_power()? {
+        if power != FB_BLANK_UNBLANK {
+            backlight.set_power(FB_BLANK_UNBLANK)?;
+        }
+    }
+    if backlight.get_brightness()? == 0 {
+        let max = backlight.get_max_brightness()?;
+        let level = state.load(backlight)?.map_or(max, |saved| saved.scaled_to(max).max(1));
+        backlight.set_brightness(level)?;
+    }
+    state.remove(backlight)
+}
+
+/// Calls `off` if the backlight is lit, otherwise `on`
+pub fn toggle(backlight: &Backlight, state: &StateDir) -> Result<()> {
+    if is_on(backlight)? {
+        off(backlight, state)
+    } else {
+        on(backlight, state)
+    }
+}
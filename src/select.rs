@@ -0,0 +1,141 @@
+//! Choosing which backlights a command applies to
+
+use std::fmt;
+
+use backlight::{Backlight, Class};
+use {ErrorKind, Result};
+
+/// Matches `text` against a shell-style glob supporting `*` and `?`
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and the text position it is currently absorbing up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// How strongly a backlight `type` is preferred when several drive the same
+/// panel, lowest first
+fn type_rank(backlight: &Backlight) -> Option<u8> {
+    match backlight.get_type().ok()?.as_str() {
+        "firmware" => Some(0),
+        "platform" => Some(1),
+        "raw" => Some(2),
+        _ => None,
+    }
+}
+
+/// Which panel a backlight lights. Firmware and platform interfaces are
+/// always for the built-in panel, as are the connectors it hangs off. When
+/// there is such an interface (`firmware`), so is a GPU's own backlight
+/// without a connector, as systemd-backlight assumes. Other devices are
+/// only known by their connector, or else stand alone.
+fn panel(backlight: &Backlight, firmware: bool) -> String {
+    match backlight.connector {
+        Some(ref c) if c.is_internal() => "internal".to_string(),
+        Some(ref c) => format!("{}-{}", c.card, c.name),
+        None => match type_rank(backlight) {
+            Some(0) | Some(1) => "internal".to_string(),
+            _ if firmware && backlight.on_pci_display() => "internal".to_string(),
+            _ => backlight.root.to_string_lossy().into_owned(),
+        },
+    }
+}
+
+/// Keeps one backlight per panel, so two interfaces to the same panel don't
+/// fight over it: firmware is preferred to platform, and platform to raw,
+/// as systemd-backlight does. Order is otherwise preserved.
+pub fn primary(backlights: Vec<Backlight>) -> Vec<Backlight> {
+    let ranks: Vec<u8> = backlights.iter().map(|bl| type_rank(bl).unwrap_or(u8::MAX)).collect();
+    let firmware = ranks.iter().any(|&rank| rank < 2);
+    let panels: Vec<String> = backlights.iter().map(|bl| panel(bl, firmware)).collect();
+    backlights.into_iter().enumerate()
+        .filter(|&(i, _)| {
+            // The first of the best-ranked devices on this panel wins
+            !(0..panels.len()).any(|j| j != i && panels[j] == panels[i] &&
+                                   (ranks[j] < ranks[i] || (ranks[j] == ranks[i] && j < i)))
+        })
+        .map(|(_, bl)| bl)
+        .collect()
+}
+
+/// Restricts which backlights an update is applied to
+#[derive(Default)]
+pub struct Selector {
+    /// Any class when `None`
+    pub class: Option<Class>,
+    pub devices: Vec<String>,
+    pub properties: Vec<(String, String)>,
+    pub drivers: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+impl Selector {
+    /// Each `--device`, `--driver` and `--output` list is an OR of its
+    /// entries, while every `--match` must hold. LEDs are named
+    /// `device:color:function`, so a `--device` pattern also matches on the
+    /// function alone, letting `kbd_backlight` stand for
+    /// `tpacpi::kbd_backlight`.
+    pub fn matches(&self, backlight: &Backlight) -> bool {
+        let any = |patterns: &[String], value: Option<&str>| {
+            patterns.is_empty() || value.is_some_and(|v| patterns.iter().any(|p| glob_match(p, v)))
+        };
+        let function = match backlight.subsystem() {
+            "leds" => backlight.sysname.rsplit(':').next(),
+            _ => None,
+        };
+        self.class.is_none_or(|c| c.subsystem() == backlight.subsystem()) &&
+            (any(&self.devices, Some(&backlight.sysname)) || any(&self.devices, function)) &&
+            any(&self.drivers, backlight.driver.as_deref()) &&
+            // Outputs can be named alone or with their card, for multi-GPU setups
+            backlight.connector.as_ref().map_or(self.outputs.is_empty(), |c| {
+                any(&self.outputs, Some(&c.name)) || any(&self.outputs, Some(&format!("{}-{}", c.card, c.name)))
+            }) &&
+            self.properties.iter().all(|(k, v)| {
+                backlight.properties.get(k).is_some_and(|actual| glob_match(v, actual))
+            })
+    }
+
+    /// The backlights that match, failing with `NoMatchingDevices` when none do
+    pub fn select<I: IntoIterator<Item = Backlight>>(&self, backlights: I) -> Result<Vec<Backlight>> {
+        let selected: Vec<Backlight> = backlights.into_iter().filter(|bl| self.matches(bl)).collect();
+        if selected.is_empty() {
+            bail!(ErrorKind::NoMatchingDevices(self.to_string()));
+        }
+        Ok(selected)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut terms = Vec::new();
+        if let Some(Class::Leds) = self.class {
+            terms.push("--class leds".to_string());
+        }
+        terms.extend(self.devices.iter().map(|d| format!("--device {}", d)));
+        terms.extend(self.properties.iter().map(|(k, v)| format!("--match {}={}", k, v)));
+        terms.extend(self.drivers.iter().map(|d| format!("--driver {}", d)));
+        terms.extend(self.outputs.iter().map(|o| format!("--output {}", o)));
+        if terms.is_empty() {
+            write!(f, "the {} subsystem", self.class.unwrap_or_default().subsystem())
+        } else {
+            write!(f, "{}", terms.join(" "))
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Brightness snapshots kept across reboots
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use backlight::{Backlight, Class};
+use curve::CurvePolicy;
+use update::Limit;
+use Result;
+
+/// A brightness as it was saved, along with the range it was saved in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Saved {
+    pub brightness: u32,
+    pub max: u32,
+}
+
+impl Saved {
+    /// The saved brightness rescaled to a device whose maximum is now `max`
+    pub fn scaled_to(&self, max: u32) -> u32 {
+        if self.max == max || self.max == 0 {
+            return self.brightness.min(max);
+        }
+        (u64::from(self.brightness) * u64::from(max) / u64::from(self.max)) as u32
+    }
+}
+
+/// A directory holding one file per saved device
+pub struct StateDir {
+    root: PathBuf,
+}
+
+impl StateDir {
+    /// Uses `root`, which is created on the first save
+    pub fn new(root: PathBuf) -> Self {
+        StateDir { root }
+    }
+
+    /// A directory of its own inside this one, for state that shouldn't be
+    /// mistaken for what `save` wrote
+    pub fn subdir(&self, name: &str) -> StateDir {
+        StateDir::new(self.root.join(name))
+    }
+
+    /// A file in this directory for state that isn't about one device
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// `$STATE_DIRECTORY` when run by systemd with `StateDirectory=`,
+    /// otherwise `$XDG_STATE_HOME/backctl` or `~/.local/state/backctl`
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(dir) = env::var_os("STATE_DIRECTORY") {
+            // systemd passes a colon-separated list when several are configured
+            let dir = dir.to_string_lossy().split(':').next().unwrap_or_default().to_string();
+            return Some(PathBuf::from(dir));
+        }
+        env::var_os("XDG_STATE_HOME").map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+            .map(|p| p.join("backctl"))
+    }
+
+    /// Devices are keyed like systemd-backlight does, by `ID_PATH` (which
+    /// survives renumbering across boots) plus subsystem and sysname
+    fn key(backlight: &Backlight) -> String {
+        let subsystem = backlight.subsystem();
+        let key = match backlight.properties.get("ID_PATH") {
+            Some(id_path) => format!("{}:{}:{}", id_path, subsystem, backlight.sysname),
+            None => format!("{}:{}", subsystem, backlight.sysname),
+        };
+        key.replace('/', "-")
+    }
+
+    /// Records the current brightness of `backlight`
+    pub fn save(&self, backlight: &Backlight) -> Result<Saved> {
+        let saved = Saved {
+            brightness: backlight.get_brightness()?,
+            max: backlight.get_max_brightness()?,
+        };
+        fs::create_dir_all(&self.root)?;
+        fs::write(self.root.join(StateDir::key(backlight)),
+                  format!("{} {}\n", saved.brightness, saved.max))?;
+        Ok(saved)
+    }
+
+    /// Forgets the saved state for `backlight`, if there is any
+    pub fn remove(&self, backlight: &Backlight) -> Result<()> {
+        let path = self.root.join(StateDir::key(backlight));
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the saved state for `backlight`, if there is any
+    pub fn load(&self, backlight: &Backlight) -> Result<Option<Saved>> {
+        let path = self.root.join(StateDir::key(backlight));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)?;
+        let mut fields = contents.split_whitespace();
+        let brightness = match fields.next() {
+            Some(b) => b.parse()?,
+            None => bail!("Empty saved state in {}", path.display()),
+        };
+        // Hand-written files may hold just the brightness
+        let max = match fields.next() {
+            Some(m) => m.parse()?,
+            None => backlight.get_max_brightness()?,
+        };
+        Ok(Some(Saved { brightness, max }))
+    }
+
+    /// Sets `backlight` back to the brightness saved for it, returning
+    /// whether there was any. It never goes below `floor`, and a screen
+    /// never comes back dark.
+    pub fn restore(&self, backlight: &Backlight, curve: &CurvePolicy, floor: &Limit) -> Result<bool> {
+        let saved = match self.load(backlight)? {
+            Some(saved) => saved,
+            None => return Ok(false),
+        };
+        let max = backlight.get_max_brightness()?;
+        let mut min = floor.for_device(backlight).to_raw(curve.resolve(backlight), max);
+        if backlight.subsystem() == Class::Backlight.subsystem() {
+            min = min.max(1);
+        }
+        backlight.set_brightness(saved.scaled_to(max).max(min).min(max))?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,271 @@
+//! Brightness changes requested on the command line
+
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use backlight::Backlight;
+use curve::{Curve, CurvePolicy};
+use select::glob_match;
+use {Error, ErrorKind, Result};
+
+/// How often `actual_brightness` is re-read while waiting for it to settle
+const VERIFY_POLL: Duration = Duration::from_millis(10);
+
+/// A brightness given either in raw units or as a percentage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Raw(u32),
+    Percent(f64),
+}
+
+impl Level {
+    /// Parses `40` as a raw level and `40%` as a percentage
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let level = match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse().ok().map(Level::Percent),
+            None => s.parse().ok().map(Level::Raw),
+        };
+        match level {
+            Some(level) => Ok(level),
+            None => bail!(ErrorKind::InvalidValue(
+                format!("Invalid level '{}', expected a raw value or a percentage such as 40%", s))),
+        }
+    }
+
+    /// Raw units for this level on a device with the given curve and maximum
+    pub fn to_raw(self, curve: Curve, max: u32) -> u32 {
+        match self {
+            Level::Raw(raw) => raw.min(max),
+            Level::Percent(percent) => curve.to_raw(percent, max),
+        }
+    }
+}
+
+/// A bound on brightness, the lowest relative updates may dim to when used
+/// as a floor and the highest any update may reach as a ceiling
+#[derive(Clone, Debug)]
+pub struct Limit {
+    pub default: Level,
+    /// Levels for devices whose sysname matches the glob, taking precedence
+    /// over `default`
+    pub devices: Vec<(String, Level)>,
+}
+
+impl Limit {
+    /// `default` for every device
+    pub fn new(default: Level) -> Self {
+        Limit { default, devices: Vec::new() }
+    }
+
+    /// The level that applies to `backlight`
+    pub fn for_device(&self, backlight: &Backlight) -> Level {
+        self.devices.iter()
+            .find(|&(pattern, _)| glob_match(pattern, &backlight.sysname))
+            .map_or(self.default, |&(_, level)| level)
+    }
+}
+
+/// Checks that the hardware actually reached a written brightness
+#[derive(Clone, Copy, Debug)]
+pub struct Verify {
+    /// How long to wait for `actual_brightness` after each write
+    pub timeout: Duration,
+    /// How many more times to write the value if it isn't reached
+    pub retries: u32,
+}
+
+impl Verify {
+    /// Waits for `actual_brightness` to read `value`, writing it again up to
+    /// `retries` times. Devices without `actual_brightness`, such as LEDs,
+    /// can't be checked and always pass.
+    pub fn check(&self, backlight: &Backlight, value: u32) -> Result<()> {
+        let mut actual = 0;
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                backlight.set_brightness(value)?;
+            }
+            let deadline = Instant::now() + self.timeout;
+            loop {
+                actual = match backlight.get_actual_brightness() {
+                    Ok(actual) => actual,
+                    Err(Error(ErrorKind::Io(ref e), _)) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+                    Err(e) => return Err(e),
+                };
+                if actual == value {
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    break;
+                }
+                thread::sleep(VERIFY_POLL);
+            }
+        }
+        bail!(ErrorKind::BrightnessNotReached(backlight.sysname.clone(), value, actual))
+    }
+}
+
+/// A brightness change, relative or absolute, in raw units or percent
+#[derive(Clone)]
+pub struct Update {
+    relative: bool,
+    percent: bool,
+    value: i32,
+    curve: CurvePolicy,
+    floor: Limit,
+    ceiling: Limit,
+    verify: Option<Verify>,
+}
+
+impl Update {
+    /// Sets the brightness to `valstr`, such as `40` or `40%`
+    pub fn set(valstr: &str) -> Result<Self> {
+        Update::new(false, valstr)
+    }
+    /// Raises the brightness by `valstr`
+    pub fn inc(valstr: &str) -> Result<Self> {
+        Update::new(true, valstr)
+    }
+    /// Lowers the brightness by `valstr`
+    pub fn dec(valstr: &str) -> Result<Self> {
+        let mut res = Update::new(true, valstr)?;
+        res.value = match res.value.checked_neg() {
+            Some(value) => value,
+            None => bail!(ErrorKind::InvalidValue(format!("Brightness '{}' is out of range", valstr))),
+        };
+        Ok(res)
+    }
+    /// An update by (`relative`) or to `valstr`, with a linear curve and
+    /// no floor or ceiling until `with_curve`, `with_floor` and
+    /// `with_ceiling` say otherwise
+    pub fn new(relative: bool, valstr: &str) -> Result<Self> {
+        Ok(Update {
+            relative,
+            percent: valstr.contains('%'),
+            value: match valstr.trim().trim_end_matches('%').parse() {
+                Ok(value) => value,
+                Err(_) => bail!(ErrorKind::InvalidValue(
+                    format!("Invalid brightness '{}', expected a raw value or a percentage such as 40%", valstr))),
+            },
+            curve: CurvePolicy::Fixed(Curve::Linear),
+            floor: Limit::new(Level::Raw(0)),
+            ceiling: Limit::new(Level::Percent(100.0)),
+            verify: None,
+        })
+    }
+
+    /// Keeps relative updates from dimming below `floor`. Absolute updates
+    /// are taken at their word, so `set 0` still turns the backlight off.
+    pub fn with_floor(mut self, floor: Limit) -> Self {
+        self.floor = floor;
+        self
+    }
+
+    /// Keeps every update from brightening past `ceiling`, including
+    /// absolute ones
+    pub fn with_ceiling(mut self, ceiling: Limit) -> Self {
+        self.ceiling = ceiling;
+        self
+    }
+
+    /// Uses `curve` to translate percentages into raw units
+    pub fn with_curve(mut self, curve: CurvePolicy) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    /// Makes `apply` wait for the hardware to report the new brightness
+    pub fn with_verify(mut self, verify: Option<Verify>) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Writes the new brightness, verifying it when `with_verify` asked to,
+    /// and hands the backlight back
+    pub fn apply(&self, backlight: Backlight) -> Result<Backlight> {
+        let value = self.target(&backlight)?;
+        backlight.set_brightness(value)?;
+        self.verify(&backlight, value)?;
+        Ok(backlight)
+    }
+
+    /// Checks the hardware reached `value`, written some other way such as
+    /// a fade, when `with_verify` asked for it
+    pub fn verify(&self, backlight: &Backlight, value: u32) -> Result<()> {
+        match self.verify {
+            Some(ref verify) => verify.check(backlight, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Computes the raw brightness `apply` would write, without writing it
+    pub fn target(&self, backlight: &Backlight) -> Result<u32> {
+        let curve = self.curve.resolve(backlight);
+        let value = if self.percent && curve != Curve::Linear {
+            self.curve_target(backlight, curve)?
+        } else {
+            self.linear_target(backlight)?
+        };
+        let max = backlight.get_max_brightness()?;
+        let ceiling = self.ceiling.for_device(backlight).to_raw(curve, max);
+        if !self.relative {
+            return Ok(value.min(ceiling));
+        }
+
+        // Step 4: Respect the floor and ceiling, without moving a device
+        // that was already outside them the wrong way
+        let floor = self.floor.for_device(backlight).to_raw(curve, max);
+        let original = backlight.get_brightness()?;
+        Ok(value.max(floor.min(original)).min(ceiling.max(original)))
+    }
+
+    fn linear_target(&self, backlight: &Backlight) -> Result<u32> {
+        // Wide enough that no u32 maximum times any i32 value overflows
+        let max = i64::from(backlight.get_max_brightness()?);
+        let mut value = i64::from(self.value);
+
+        // Step 1: Percent to brightness-units
+        if self.percent {
+            value = max * value / 100;
+        }
+
+        // Step 2: Relative to absolute
+        if self.relative {
+            let original = i64::from(backlight.get_brightness()?);
+            value += original;
+        }
+
+        // Step 3: Clamp to min/max
+        if value > max {
+            value = max;
+        }
+        if value < 0 {
+            value = 0;
+        }
+
+        Ok(value as u32)
+    }
+
+    /// Percent updates along a non-linear curve work in percent space, so
+    /// `inc 5%` moves the same perceived distance anywhere in the range
+    fn curve_target(&self, backlight: &Backlight, curve: Curve) -> Result<u32> {
+        let max = backlight.get_max_brightness()?;
+        let mut percent = f64::from(self.value);
+        if !self.relative {
+            return Ok(curve.to_raw(percent, max));
+        }
+
+        let original = backlight.get_brightness()?.min(max);
+        percent += curve.to_percent(original, max);
+        let mut value = curve.to_raw(percent, max);
+        // The curve is flat enough at the bottom that a small step can round
+        // back to where we started; always move at least one unit
+        if value == original && self.value > 0 && original < max {
+            value += 1;
+        } else if value == original && self.value < 0 && original > 0 {
+            value -= 1;
+        }
+        Ok(value)
+    }
+}
@@ -0,0 +1,90 @@
+//! Streaming brightness changes for status bars
+
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+use backlight::{Backlight, Class, Source};
+use curve::CurvePolicy;
+use hotplug;
+use output::{Format, Report};
+use Result;
+
+/// What each line reports
+pub struct Watch {
+    /// How often devices are re-read when no udev event arrives
+    pub interval: Duration,
+    pub raw: bool,
+    pub percent: bool,
+    pub format: Format,
+}
+
+impl Watch {
+    /// Prints the current brightness of every device and then one line
+    /// each time a device changes. Only returns on error, such as the
+    /// reader closing `out`.
+    ///
+    /// Values are read from `actual_brightness` so changes the firmware
+    /// makes behind our back (hotkeys, ambient light) are seen too. With
+    /// udev the kernel's `change` events wake us immediately; polling at
+    /// `interval` catches drivers that change silently.
+    pub fn run(&self, backlights: &[Backlight], source: &Source, curve: &CurvePolicy,
+               out: &mut dyn Write) -> Result<()> {
+        let mut monitor = match *source {
+            Source::Udev => {
+                let classes: Vec<Class> = Class::ALL.iter().cloned()
+                    .filter(|c| backlights.iter().any(|bl| bl.subsystem() == c.subsystem()))
+                    .collect();
+                Some(hotplug::monitor(&classes)?)
+            }
+            Source::Sysfs(_) => None,
+        };
+
+        let mut last: Vec<Option<u32>> = vec![None; backlights.len()];
+        loop {
+            for (bl, last) in backlights.iter().zip(last.iter_mut()) {
+                // The device may be mid-removal; it'll either come back or
+                // stay quiet
+                let value = match bl.get_actual_brightness().or_else(|_| bl.get_brightness()) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+                if *last != Some(value) {
+                    *last = Some(value);
+                    self.report(bl, value, curve, out)?;
+                }
+            }
+
+            match monitor {
+                Some(ref mut socket) => {
+                    if hotplug::wait_readable(socket.as_raw_fd(), Some(self.interval))? {
+                        // We re-read everything anyway, so just drain the queue
+                        for _ in socket.by_ref() {}
+                    }
+                }
+                None => thread::sleep(self.interval),
+            }
+        }
+    }
+
+    fn report(&self, bl: &Backlight, value: u32, curve: &CurvePolicy, out: &mut dyn Write) -> Result<()> {
+        let report = Report::new(bl, value, curve)?;
+        if self.format != Format::Text {
+            report.write(self.format, out)?;
+        } else {
+            // Neither flag means both, as with get
+            let both = !self.raw && !self.percent;
+            let mut fields = vec![bl.sysname.clone()];
+            if self.raw || both {
+                fields.push(value.to_string());
+            }
+            if self.percent || both {
+                fields.push(format!("{}%", report.percent));
+            }
+            writeln!(out, "{}", fields.join(" "))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+extern crate backctl;
+
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use backctl::als::{Learned, LuxCurve, Sensor, Smoother};
+use common::FakeSysfs;
+
+fn wait_for<F: Fn() -> bool>(condition: F) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn reads_lux() {
+    let sys = FakeSysfs::new().light_sensor(40, 0.5, 10.0);
+    let sensor = Sensor::find(sys.root()).unwrap();
+    assert_eq!(sensor.path, sys.sensor_path());
+    assert_eq!(sensor.read_lux().unwrap(), 25.0);
+
+    assert!(Sensor::find(FakeSysfs::new().root()).is_err());
+}
+
+#[test]
+fn curve_interpolates_in_log_lux() {
+    let curve = LuxCurve::parse("0:10, 99:50, 9999:100").unwrap();
+    assert_eq!(curve.percent(0.0), 10.0);
+    assert_eq!(curve.percent(99.0), 50.0);
+    assert_eq!(curve.percent(50000.0), 100.0);
+    // Halfway between 100 and 10000 lux to the eye is 1000
+    assert!((curve.percent(999.0) - 75.0).abs() < 1e-9);
+
+    assert!(LuxCurve::parse("100:50,10:20").is_err());
+    assert!(LuxCurve::parse("0:150").is_err());
+    assert!(LuxCurve::parse("bright").is_err());
+}
+
+#[test]
+fn smoother_holds_small_changes() {
+    let curve = LuxCurve::parse("0:0,100:100").unwrap();
+    let mut smoother = Smoother::new(0.5, 5.0);
+    assert_eq!(smoother.sample(0.0, &curve), Some(0.0));
+    // One bright sample only moves the average halfway
+    assert_eq!(smoother.sample(100.0, &curve), Some(curve.percent(50.0)));
+    // Readings around the average don't move the brightness
+    assert_eq!(smoother.sample(50.0, &curve), None);
+    assert_eq!(smoother.sample(48.0, &curve), None);
+    assert_eq!(smoother.sample(0.0, &curve), Some(curve.percent(24.5)));
+}
+
+#[test]
+fn learns_from_choices() {
+    let mut learned = Learned::new(LuxCurve::parse("0:10,100:50,10000:100").unwrap());
+    learned.record(100.0, 80.0);
+    assert_eq!(learned.curve().percent(100.0), 80.0);
+    assert_eq!(learned.curve().percent(0.0), 10.0);
+    assert_eq!(learned.curve().percent(10000.0), 100.0);
+
+    // Dimmer in brighter light contradicts the earlier choice, which goes
+    learned.record(1000.0, 60.0);
+    assert_eq!(learned.curve().percent(1000.0), 60.0);
+    assert_eq!(learned.curve().percent(100.0), 50.0);
+
+    // The newest choice at about the same light level wins
+    learned.record(1200.0, 65.0);
+    assert_eq!(learned.curve().percent(1200.0), 65.0);
+    assert!(learned.curve().percent(1000.0) < 65.0);
+
+    let sys = FakeSysfs::new();
+    let path = sys.root().join("state/auto-curve");
+    learned.save(&path).unwrap();
+    let loaded = Learned::load(LuxCurve::parse("0:10,100:50,10000:100").unwrap(), &path).unwrap();
+    assert_eq!(loaded.curve(), learned.curve());
+}
+
+#[test]
+fn daemon_follows_sensor() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 0, 1000)
+        .light_sensor(100, 1.0, 0.0)
+        .config("[auto]\nsmoothing = 1\n");
+    let _daemon = sys.daemon(&["--auto", "--auto-interval", "10ms", "--auto-fade", "0",
+                               "--auto-curve", "0:10,100:50,10000:100"]);
+    wait_for(|| sys.brightness("panel") == 500);
+
+    sys.illuminance(10000);
+    wait_for(|| sys.brightness("panel") == 1000);
+
+    // Too small a change to act on
+    sys.illuminance(9000);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(sys.brightness("panel"), 1000);
+
+    sys.illuminance(0);
+    wait_for(|| sys.brightness("panel") == 100);
+}
+
+#[test]
+fn daemon_keeps_manual_changes() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 0, 1000)
+        .light_sensor(100, 1.0, 0.0)
+        .config("[auto]\nsmoothing = 1\ncurve = \"0:10,100:50,10000:100\"\n");
+    let state = sys.root().join("state");
+    let args = ["--auto", "--auto-interval", "10ms", "--auto-fade", "0", "--state-dir", state.to_str().unwrap()];
+    let daemon = sys.daemon(&args);
+    wait_for(|| sys.brightness("panel") == 500);
+
+    sys.ok(&["set", "80%"]);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(sys.brightness("panel"), 800);
+
+    // Back in the same light, the screen returns to the chosen level
+    sys.illuminance(10000);
+    wait_for(|| sys.brightness("panel") == 1000);
+    sys.illuminance(100);
+    wait_for(|| sys.brightness("panel") == 800);
+
+    // And it's remembered across restarts
+    drop(daemon);
+    sys.ok(&["--no-daemon", "set", "0"]);
+    let _daemon = sys.daemon(&args);
+    wait_for(|| sys.brightness("panel") == 800);
+}
+
+#[test]
+fn daemon_doesnt_learn_off() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 0, 1000)
+        .light_sensor(100, 1.0, 0.0)
+        .config("floor = \"5%\"\n[auto]\nsmoothing = 1\ncurve = \"0:10,100:50,10000:100\"\n");
+    let state = sys.root().join("state");
+    let _daemon = sys.daemon(&["--auto", "--auto-interval", "10ms", "--auto-fade", "0",
+                               "--state-dir", state.to_str().unwrap()]);
+    wait_for(|| sys.brightness("panel") == 500);
+
+    sys.ok(&["set", "0"]);
+    sys.ok(&["set", "2%"]);
+    thread::sleep(Duration::from_millis(100));
+    assert!(!state.join("auto-curve").exists());
+
+    // The curve is unchanged, so this light brings back the old level
+    sys.illuminance(10000);
+    wait_for(|| sys.brightness("panel") == 1000);
+    sys.illuminance(100);
+    wait_for(|| sys.brightness("panel") == 500);
+}
+
+#[test]
+fn daemon_survives_bad_samples() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 0, 1000)
+        .light_sensor(100, 1.0, 0.0)
+        .config("[auto]\nsmoothing = 1\n");
+    let _daemon = sys.daemon(&["--auto", "--auto-interval", "10ms", "--auto-fade", "0",
+                               "--auto-curve", "0:10,100:50,10000:100"]);
+    wait_for(|| sys.brightness("panel") == 500);
+
+    fs::write(sys.sensor_path().join("in_illuminance_raw"), "").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    sys.illuminance(10000);
+    wait_for(|| sys.brightness("panel") == 1000);
+}
+
+#[test]
+fn daemon_keeps_floor() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 500, 1000)
+        .light_sensor(0, 1.0, 0.0)
+        .config("floor = \"5%\"\n[auto]\nsmoothing = 1\n");
+    let _daemon = sys.daemon(&["--auto", "--auto-interval", "10ms", "--auto-fade", "0",
+                               "--auto-curve", "0:0,100:50"]);
+    wait_for(|| sys.brightness("panel") != 500);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(sys.brightness("panel"), 50);
+}
+
+#[test]
+fn manual_changes_stop_fades() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 0, 1000)
+        .light_sensor(100, 1.0, 0.0)
+        .config("[auto]\nsmoothing = 1\n");
+    let _daemon = sys.daemon(&["--auto", "--auto-interval", "10ms", "--auto-fade", "3s",
+                               "--auto-curve", "0:10,100:50,10000:100"]);
+    wait_for(|| sys.brightness("panel") > 0);
+
+    // Doesn't wait for the fade, and the fade doesn't carry on over it
+    let start = Instant::now();
+    sys.ok(&["set", "20%"]);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(sys.brightness("panel"), 200);
+}
+
+#[test]
+fn daemon_needs_sensor() {
+    let sys = FakeSysfs::new().backlight("panel", 0, 1000);
+    let output = sys.run(&["daemon", "--auto"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No ambient light sensor"));
+}
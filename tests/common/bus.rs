@@ -0,0 +1,162 @@
+//! A stand-in for the system bus and logind, answering just `Hello` and
+//! `Session.SetBrightness`
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A `SetBrightness` call as received
+#[derive(Debug, PartialEq)]
+pub struct Call {
+    pub subsystem: String,
+    pub name: String,
+    pub value: u32,
+}
+
+pub struct FakeBus {
+    calls: Arc<Mutex<Vec<Call>>>,
+}
+
+impl FakeBus {
+    /// Listens on `socket`, writing accepted brightness into the matching
+    /// device under `sysfs` like logind would, or answering every call with
+    /// `error` when given
+    pub fn start(socket: &Path, sysfs: &Path, error: Option<&'static str>) -> Self {
+        let listener = UnixListener::bind(socket).unwrap();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let sysfs = sysfs.to_path_buf();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let recorded = recorded.clone();
+                let sysfs = sysfs.clone();
+                thread::spawn(move || serve(stream, &sysfs, error, &recorded));
+            }
+        });
+        FakeBus { calls }
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().drain(..).collect()
+    }
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&buf[pos..pos + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn align(pos: usize, n: usize) -> usize {
+    pos.div_ceil(n) * n
+}
+
+/// Reads a string at `pos`, returning it and the position after it
+fn string_at(buf: &[u8], pos: usize) -> (String, usize) {
+    let pos = align(pos, 4);
+    let len = u32_at(buf, pos) as usize;
+    (String::from_utf8(buf[pos + 4..pos + 4 + len].to_vec()).unwrap(), pos + 5 + len)
+}
+
+fn serve(stream: UnixStream, sysfs: &Path, error: Option<&str>, calls: &Mutex<Vec<Call>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.starts_with("\0AUTH EXTERNAL "), "unexpected auth {:?}", line);
+    writer.write_all(b"OK 0123456789abcdef0123456789abcdef\r\n").unwrap();
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "BEGIN\r\n");
+
+    loop {
+        let mut fixed = [0; 16];
+        if reader.read_exact(&mut fixed).is_err() {
+            return;
+        }
+        assert_eq!(fixed[0], b'l');
+        let body_len = u32_at(&fixed, 4) as usize;
+        let serial = u32_at(&fixed, 8);
+        let fields_len = u32_at(&fixed, 12) as usize;
+        let body_start = align(16 + fields_len, 8);
+        let mut msg = fixed.to_vec();
+        msg.resize(body_start + body_len, 0);
+        reader.read_exact(&mut msg[16..]).unwrap();
+
+        // Every field the client sends holds a string, object path or signature
+        let mut member = String::new();
+        let mut pos = 16;
+        while pos < 16 + fields_len {
+            pos = align(pos, 8);
+            let code = msg[pos];
+            let sig = msg[pos + 2];
+            pos += 4;
+            if sig == b'g' {
+                pos += msg[pos] as usize + 2;
+            } else {
+                let (value, next) = string_at(&msg, pos);
+                if code == 3 {
+                    member = value;
+                }
+                pos = next;
+            }
+        }
+
+        if member == "SetBrightness" {
+            let (subsystem, pos) = string_at(&msg, body_start);
+            let (name, pos) = string_at(&msg, pos);
+            let value = u32_at(&msg, align(pos, 4));
+            if error.is_none() {
+                // Tests that make brightness unwritable to force the fallback
+                // only look at the calls
+                let _ = fs::write(sysfs.join("class").join(&subsystem).join(&name).join("brightness"),
+                                  value.to_string());
+            }
+            calls.lock().unwrap().push(Call { subsystem, name, value });
+        }
+
+        let reply = match (member.as_str(), error) {
+            ("SetBrightness", Some(error)) => reply(3, serial, Some(error)),
+            _ => reply(2, serial, None),
+        };
+        writer.write_all(&reply).unwrap();
+    }
+}
+
+/// A method return (kind 2) or an error (kind 3) carrying a message
+fn reply(kind: u8, serial: u32, error: Option<&str>) -> Vec<u8> {
+    let mut msg = vec![b'l', kind, 1, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0];
+    // REPLY_SERIAL
+    msg.extend_from_slice(&[5, 1, b'u', 0]);
+    msg.extend_from_slice(&serial.to_le_bytes());
+    let mut body = Vec::new();
+    if let Some(error) = error {
+        // ERROR_NAME
+        msg.extend_from_slice(&[4, 1, b's', 0]);
+        msg.extend_from_slice(&(error.len() as u32).to_le_bytes());
+        msg.extend_from_slice(error.as_bytes());
+        msg.push(0);
+        while msg.len() % 8 != 0 {
+            msg.push(0);
+        }
+        // SIGNATURE
+        msg.extend_from_slice(&[8, 1, b'g', 0, 1, b's', 0]);
+
+        let text = "Permission denied";
+        body.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        body.extend_from_slice(text.as_bytes());
+        body.push(0);
+    }
+    let fields_len = (msg.len() - 16) as u32;
+    msg[12..16].copy_from_slice(&fields_len.to_le_bytes());
+    msg[4..8].copy_from_slice(&(body.len() as u32).to_le_bytes());
+    while msg.len() % 8 != 0 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&body);
+    msg
+}
@@ -0,0 +1,231 @@
+//! A fake sysfs tree for driving the backctl binary without hardware
+
+#![allow(dead_code)]
+
+pub mod bus;
+
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Reads a number from an attribute file, trying again if a daemon was
+/// caught halfway through rewriting it, which real sysfs never shows
+fn read_value(path: &Path) -> u32 {
+    let start = Instant::now();
+    loop {
+        match fs::read_to_string(path).unwrap().trim().parse() {
+            Ok(value) => return value,
+            Err(e) => assert!(start.elapsed() < Duration::from_secs(1), "{}: {}", path.display(), e),
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// A temporary directory laid out like `/sys`, removed on drop
+pub struct FakeSysfs {
+    root: PathBuf,
+}
+
+impl FakeSysfs {
+    pub fn new() -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let root = std::env::temp_dir()
+            .join(format!("backctl-test-{}-{}", std::process::id(), id));
+        if root.exists() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+        fs::create_dir_all(root.join("class/backlight")).unwrap();
+        FakeSysfs { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn device_path(&self, name: &str) -> PathBuf {
+        self.root.join("class/backlight").join(name)
+    }
+
+    /// Adds a backlight with the given current and maximum brightness
+    pub fn backlight(self, name: &str, brightness: u32, max: u32) -> Self {
+        let dir = self.device_path(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("brightness"), format!("{}\n", brightness)).unwrap();
+        fs::write(dir.join("actual_brightness"), format!("{}\n", brightness)).unwrap();
+        fs::write(dir.join("max_brightness"), format!("{}\n", max)).unwrap();
+        fs::write(dir.join("type"), "raw\n").unwrap();
+        self
+    }
+
+    /// Adds a backlight under the DRM connector `card0-<connector>` of a
+    /// GPU, linked into the class directory as the kernel does
+    pub fn connected_backlight(self, name: &str, connector: &str, brightness: u32, max: u32) -> Self {
+        let dir = self.root.join("devices/pci0000:00/0000:00:02.0/drm/card0")
+            .join(format!("card0-{}", connector))
+            .join(name);
+        fs::create_dir_all(&dir).unwrap();
+        std::os::unix::fs::symlink(&dir, self.device_path(name)).unwrap();
+        self.backlight(name, brightness, max)
+    }
+
+    /// Adds a backlight straight under a PCI display controller with no
+    /// connector, as amdgpu, nouveau and radeon register theirs
+    pub fn gpu_backlight(self, name: &str, brightness: u32, max: u32) -> Self {
+        let gpu = self.root.join("devices/pci0000:00/0000:01:00.0");
+        let dir = gpu.join("backlight").join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::create_dir_all(self.root.join("bus/pci")).unwrap();
+        if !gpu.join("subsystem").exists() {
+            std::os::unix::fs::symlink(self.root.join("bus/pci"), gpu.join("subsystem")).unwrap();
+            fs::write(gpu.join("class"), "0x030000\n").unwrap();
+        }
+        std::os::unix::fs::symlink(&dir, self.device_path(name)).unwrap();
+        self.backlight(name, brightness, max)
+    }
+
+    pub fn led_path(&self, name: &str) -> PathBuf {
+        self.root.join("class/leds").join(name)
+    }
+
+    /// Adds an LED, which unlike a backlight has no `actual_brightness` or
+    /// `type`
+    pub fn led(self, name: &str, brightness: u32, max: u32) -> Self {
+        let dir = self.led_path(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("brightness"), format!("{}\n", brightness)).unwrap();
+        fs::write(dir.join("max_brightness"), format!("{}\n", max)).unwrap();
+        self
+    }
+
+    /// Writes an arbitrary attribute file for an existing backlight
+    pub fn attribute(self, name: &str, attribute: &str, value: &str) -> Self {
+        fs::write(self.device_path(name).join(attribute), format!("{}\n", value)).unwrap();
+        self
+    }
+
+    /// Replaces a backlight's `brightness` with a link to `target`, such as
+    /// a kernel file that refuses writes even from root
+    pub fn link_brightness(self, name: &str, target: &str) -> Self {
+        let path = self.device_path(name).join("brightness");
+        fs::remove_file(&path).unwrap();
+        std::os::unix::fs::symlink(target, path).unwrap();
+        self
+    }
+
+    pub fn brightness(&self, name: &str) -> u32 {
+        read_value(&self.device_path(name).join("brightness"))
+    }
+
+    /// Where the system bus is expected, so tests never reach the real one
+    pub fn bus_path(&self) -> PathBuf {
+        self.root.join("system_bus_socket")
+    }
+
+    /// The IIO device directory of the ambient light sensor
+    pub fn sensor_path(&self) -> PathBuf {
+        self.root.join("bus/iio/devices/iio:device0")
+    }
+
+    /// Adds an ambient light sensor reading `raw`, which backctl turns into
+    /// lux as `(raw + offset) * scale`
+    pub fn light_sensor(self, raw: u32, scale: f64, offset: f64) -> Self {
+        let dir = self.sensor_path();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("in_illuminance_scale"), format!("{}\n", scale)).unwrap();
+        fs::write(dir.join("in_illuminance_offset"), format!("{}\n", offset)).unwrap();
+        self.illuminance(raw);
+        self
+    }
+
+    /// Changes what the light sensor reads, all at once so the daemon never
+    /// sees the file half written
+    pub fn illuminance(&self, raw: u32) {
+        let path = self.sensor_path().join("in_illuminance_raw");
+        let temp = path.with_extension("new");
+        fs::write(&temp, format!("{}\n", raw)).unwrap();
+        fs::rename(&temp, &path).unwrap();
+    }
+
+    /// Where backctl finds the user's configuration
+    pub fn config_path(&self) -> PathBuf {
+        self.root.join("backctl/config.toml")
+    }
+
+    /// Writes the user's configuration
+    pub fn config(self, text: &str) -> Self {
+        let path = self.config_path();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+        self
+    }
+
+    pub fn led_brightness(&self, name: &str) -> u32 {
+        read_value(&self.led_path(name).join("brightness"))
+    }
+
+    /// A backctl command using this tree, with its runtime directory (and
+    /// so the daemon socket), configuration, state and system bus inside it
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_backctl"));
+        command.env("XDG_RUNTIME_DIR", &self.root)
+            .env("XDG_CONFIG_HOME", &self.root)
+            .env("XDG_STATE_HOME", &self.root)
+            .env("DBUS_SYSTEM_BUS_ADDRESS", format!("unix:path={}", self.bus_path().display()))
+            .arg("--sysfs-root")
+            .arg(&self.root)
+            .args(args);
+        command
+    }
+
+    /// Runs backctl against this tree
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /// Starts `backctl daemon` on this tree, waiting until it is listening
+    pub fn daemon(&self, args: &[&str]) -> Daemon {
+        let mut all = vec!["daemon"];
+        all.extend_from_slice(args);
+        let child = self.command(&all).spawn().unwrap();
+        let socket = self.root.join("backctl.sock");
+        let start = Instant::now();
+        // The socket file appears before the daemon listens on it
+        while UnixStream::connect(&socket).is_err() {
+            assert!(start.elapsed() < Duration::from_secs(5), "daemon never started listening");
+            thread::sleep(Duration::from_millis(10));
+        }
+        Daemon { child }
+    }
+
+    /// Runs backctl against this tree, asserting success and returning stdout
+    pub fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(output.status.success(), "backctl {:?} failed: {}",
+                args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+impl Drop for FakeSysfs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// A running daemon, killed on drop
+pub struct Daemon {
+    child: Child,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
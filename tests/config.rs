@@ -0,0 +1,135 @@
+mod common;
+
+use common::FakeSysfs;
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn aliases_floors_and_step() {
+    let sys = FakeSysfs::new()
+        .backlight("intel_backlight", 500, 1000)
+        .backlight("ddcci5", 50, 100)
+        .config("step = \"10%\"\n\
+                 [devices.intel_backlight]\n\
+                 alias = \"screen\"\n\
+                 floor = \"20%\"\n");
+
+    sys.ok(&["--device", "screen", "inc"]);
+    assert_eq!(sys.brightness("intel_backlight"), 600);
+    assert_eq!(sys.brightness("ddcci5"), 50);
+
+    sys.ok(&["--device", "screen", "dec", "100%"]);
+    assert_eq!(sys.brightness("intel_backlight"), 200);
+
+    // Flags beat the config
+    sys.ok(&["--device", "screen", "dec", "100%", "--floor", "5"]);
+    assert_eq!(sys.brightness("intel_backlight"), 5);
+    sys.ok(&["--device", "screen", "inc", "10"]);
+    assert_eq!(sys.brightness("intel_backlight"), 15);
+
+    // Per-device flags take aliases too
+    sys.ok(&["--device", "screen", "set", "500"]);
+    sys.ok(&["--device", "screen", "dec", "100%", "--floor", "screen=10%"]);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+}
+
+#[test]
+fn no_step() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let output = sys.run(&["inc"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(sys.brightness("panel"), 10);
+}
+
+#[test]
+fn ceilings() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 10, 100)
+        .config("ceiling = \"80%\"\n\
+                 [devices.panel]\n\
+                 ceiling = \"60\"\n");
+
+    sys.ok(&["set", "100%"]);
+    assert_eq!(sys.brightness("panel"), 60);
+    sys.ok(&["inc", "50"]);
+    assert_eq!(sys.brightness("panel"), 60);
+
+    sys.ok(&["set", "100%", "--ceiling", "panel=90"]);
+    assert_eq!(sys.brightness("panel"), 90);
+    sys.ok(&["set", "100%", "--ceiling", "100%"]);
+    assert_eq!(sys.brightness("panel"), 100);
+}
+
+#[test]
+fn ignored_devices() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .backlight("intel_backlight", 100, 1000)
+        .config("ignore = [\"acpi_video*\"]\n");
+
+    sys.ok(&["set", "50%"]);
+    assert_eq!(sys.brightness("intel_backlight"), 500);
+    assert_eq!(sys.brightness("acpi_video0"), 5);
+    assert!(!sys.ok(&["list"]).contains("acpi_video0"));
+}
+
+#[test]
+fn per_device_curve() {
+    let sys = FakeSysfs::new()
+        .backlight("intel_backlight", 0, 1000)
+        .attribute("intel_backlight", "scale", "linear")
+        .config("[devices.intel_backlight]\ncurve = \"linear\"\n");
+
+    sys.ok(&["set", "50%"]);
+    assert_eq!(sys.brightness("intel_backlight"), 500);
+    sys.ok(&["set", "50%", "--curve", "auto"]);
+    assert_eq!(sys.brightness("intel_backlight"), 218);
+}
+
+#[test]
+fn explicit_file() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let path = sys.root().join("other.toml");
+    std::fs::write(&path, "step = \"7\"\n").unwrap();
+
+    sys.ok(&["--config", path.to_str().unwrap(), "inc"]);
+    assert_eq!(sys.brightness("panel"), 17);
+
+    let output = sys.run(&["--config", "/nonexistent/backctl.toml", "get"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn check() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let path = sys.config_path().display().to_string();
+
+    let output = sys.run(&["config", "check"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("No configuration found"), "{}", stdout(&output));
+
+    let sys = sys.config("floor = \"5%\"\n[devices.panel]\nalias = \"screen\"\n[devices.hdmi]\nceiling = \"90%\"\n");
+    assert_eq!(sys.ok(&["config", "check"]),
+               format!("{0}: warning: devices.\"hdmi\" matches no device\n{0}: ok\n", path));
+
+    let sys = sys.config("floor = \"dim\"\nstep = \"5%\"\n[devices.panel]\ncurve = \"cubic\"\n");
+    let output = sys.run(&["config", "check"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output),
+               format!("{0}: error: floor: Invalid level 'dim', expected a raw value or a percentage such as 40%\n\
+                        {0}: error: devices.\"panel\".curve: Unknown curve 'cubic', expected linear, gamma or log\n",
+                       path));
+
+    // Other commands refuse to run with a broken configuration
+    let output = sys.run(&["get"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("hint: `backctl config check`"));
+
+    let sys = sys.config("florr = 1\n");
+    let output = sys.run(&["config", "check"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout(&output).contains("unknown field `florr`"), "{}", stdout(&output));
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::FakeSysfs;
+
+#[test]
+fn gamma_set_and_get() {
+    let sys = FakeSysfs::new().backlight("panel", 0, 100);
+    sys.ok(&["set", "50%", "--curve", "gamma", "--gamma", "2"]);
+    assert_eq!(sys.brightness("panel"), 25);
+    assert_eq!(sys.ok(&["get", "--percent", "--curve", "gamma", "--gamma", "2"]), "50%\n");
+    assert_eq!(sys.ok(&["get", "--percent"]), "25%\n");
+}
+
+#[test]
+fn log_set() {
+    let sys = FakeSysfs::new().backlight("panel", 0, 255);
+    sys.ok(&["set", "50%", "--curve", "log"]);
+    assert_eq!(sys.brightness("panel"), 15);
+    sys.ok(&["set", "100%", "--curve", "log"]);
+    assert_eq!(sys.brightness("panel"), 255);
+}
+
+#[test]
+fn dec_near_bottom_stays_lit() {
+    let sys = FakeSysfs::new().backlight("panel", 30, 1000);
+    sys.ok(&["dec", "5%", "--curve", "gamma"]);
+    assert_eq!(sys.brightness("panel"), 16);
+}
+
+#[test]
+fn small_steps_always_move() {
+    let sys = FakeSysfs::new().backlight("panel", 0, 1000);
+    sys.ok(&["inc", "1%", "--curve", "gamma"]);
+    assert_eq!(sys.brightness("panel"), 1);
+    sys.ok(&["dec", "1%", "--curve", "gamma", "--allow-off"]);
+    assert_eq!(sys.brightness("panel"), 0);
+}
+
+#[test]
+fn raw_values_ignore_curve() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    sys.ok(&["inc", "5", "--curve", "log"]);
+    assert_eq!(sys.brightness("panel"), 15);
+}
+
+#[test]
+fn auto_follows_kernel_scale() {
+    let sys = FakeSysfs::new()
+        .backlight("linear", 0, 100)
+        .attribute("linear", "scale", "linear")
+        .backlight("nonlinear", 0, 100)
+        .attribute("nonlinear", "scale", "non-linear")
+        .backlight("unknown", 0, 100)
+        .attribute("unknown", "scale", "unknown");
+    sys.ok(&["set", "50%", "--gamma", "2"]);
+    assert_eq!(sys.brightness("linear"), 25);
+    assert_eq!(sys.brightness("nonlinear"), 50);
+    assert_eq!(sys.brightness("unknown"), 50);
+    assert_eq!(sys.ok(&["get", "--percent", "--gamma", "2"]), "linear 50%\nnonlinear 50%\nunknown 50%\n");
+}
+
+#[test]
+fn scale_override() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 0, 100)
+        .attribute("panel", "scale", "unknown");
+    sys.ok(&["set", "50%", "--gamma", "2", "--scale-override", "pan*=linear"]);
+    assert_eq!(sys.brightness("panel"), 25);
+}
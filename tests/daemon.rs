@@ -0,0 +1,122 @@
+mod common;
+
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::FakeSysfs;
+
+#[test]
+fn forwards_to_daemon() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _daemon = sys.daemon(&["--no-hotplug"]);
+
+    // Without hotplug the daemon only knows the devices present when it
+    // started, so a device added afterwards shows whether requests went
+    // through it
+    let sys = sys.backlight("late", 10, 100);
+    sys.ok(&["set", "50%"]);
+    assert_eq!(sys.brightness("panel"), 50);
+    assert_eq!(sys.brightness("late"), 10);
+
+    sys.ok(&["inc", "5"]);
+    assert_eq!(sys.ok(&["get", "--raw"]), "55\n");
+
+    sys.ok(&["--no-daemon", "set", "20"]);
+    assert_eq!(sys.brightness("panel"), 20);
+    assert_eq!(sys.brightness("late"), 20);
+}
+
+#[test]
+fn daemon_reports_errors() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _daemon = sys.daemon(&[]);
+    let output = sys.run(&["set", "--device", "missing", "0"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No backlight devices matched --device missing"));
+}
+
+#[test]
+fn refuses_other_trees() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _daemon = sys.daemon(&["--no-hotplug"]);
+    let other = FakeSysfs::new().backlight("panel", 10, 100);
+
+    // The socket is found through the runtime directory of the first tree
+    let output = other.command(&["set", "77"]).env("XDG_RUNTIME_DIR", sys.root()).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("different --sysfs-root"));
+    assert_eq!(sys.brightness("panel"), 10);
+    assert_eq!(other.brightness("panel"), 10);
+
+    let output = sys.run(&["--backend", "logind", "set", "77"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(sys.brightness("panel"), 10);
+}
+
+#[test]
+fn silent_client_doesnt_block_others() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _daemon = sys.daemon(&["--no-hotplug"]);
+    let _idle = UnixStream::connect(sys.root().join("backctl.sock")).unwrap();
+    sys.ok(&["set", "30"]);
+    assert_eq!(sys.brightness("panel"), 30);
+}
+
+#[test]
+fn refuses_second_daemon() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _daemon = sys.daemon(&[]);
+    let output = sys.run(&["daemon"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already listening"));
+}
+
+fn wait_for<F: Fn() -> bool>(condition: F) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn hotplugged_devices_are_served() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _daemon = sys.daemon(&[]);
+    let sys = sys.backlight("late", 10, 100);
+    let get = || sys.run(&["get", "--raw"]).stdout;
+    wait_for(|| get() == b"panel 10\nlate 10\n");
+    sys.ok(&["set", "30"]);
+    assert_eq!(sys.brightness("late"), 30);
+
+    fs::remove_dir_all(sys.device_path("late")).unwrap();
+    wait_for(|| get() == b"30\n");
+}
+
+#[test]
+fn reapplies_last_brightness() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _daemon = sys.daemon(&["--reapply"]);
+    sys.ok(&["set", "40%"]);
+    let sys = sys.backlight("late", 10, 1000);
+    wait_for(|| sys.brightness("late") == 400);
+}
+
+#[test]
+fn reapplies_only_to_primary_devices_within_ceiling() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .backlight("external", 10, 100)
+        .config("[devices.late]\nceiling = \"20%\"\n");
+    let _daemon = sys.daemon(&["--reapply"]);
+    sys.ok(&["--all", "set", "40%"]);
+
+    // A late second interface to the built-in panel is left alone
+    let sys = sys.connected_backlight("intel_backlight", "eDP-1", 100, 1000);
+    let sys = sys.backlight("late", 10, 1000);
+    wait_for(|| sys.brightness("late") == 200);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use std::fs;
+
+use common::FakeSysfs;
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn invalid_value() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let output = sys.run(&["set", "bright"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr(&output),
+               "Invalid brightness 'bright', expected a raw value or a percentage such as 40%\n");
+
+    let output = sys.run(&["inc", "5", "--fade", "soon"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr(&output), "Invalid duration 'soon'\n");
+    assert_eq!(sys.brightness("panel"), 10);
+}
+
+#[test]
+fn usage_error() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let output = sys.run(&["brighten"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("brighten"));
+    assert!(sys.run(&["--help"]).status.success());
+}
+
+#[test]
+fn no_devices() {
+    let sys = FakeSysfs::new();
+    let output = sys.run(&["get"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(stderr(&output), "No backlight devices matched the backlight subsystem\n\
+                                 hint: `backctl list` shows the devices available\n");
+}
+
+#[test]
+fn permission_denied() {
+    // Read-only /proc/sys entries refuse writes with EACCES even to root
+    let sys = FakeSysfs::new()
+        .backlight("panel", 10, 100)
+        .link_brightness("panel", "/proc/sys/kernel/osrelease");
+    let output = sys.run(&["--backend", "sysfs", "set", "40"]);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).starts_with("Permission denied writing "), "{}", stderr(&output));
+    assert!(stderr(&output).contains("\nhint: run backctl from a logind session"), "{}", stderr(&output));
+}
+
+#[test]
+fn write_rejected() {
+    // Writing a process's stat file fails with EINVAL, as drivers do
+    let sys = FakeSysfs::new()
+        .backlight("panel", 10, 100)
+        .link_brightness("panel", "/proc/self/stat");
+    let output = sys.run(&["set", "40"]);
+    assert_eq!(output.status.code(), Some(6));
+    assert!(stderr(&output).starts_with("The driver rejected 40 written to "), "{}", stderr(&output));
+    assert!(stderr(&output).contains("\nhint: values above max_brightness"), "{}", stderr(&output));
+}
+
+#[test]
+fn daemon_keeps_exit_codes() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _daemon = sys.daemon(&["--no-hotplug"]);
+
+    let output = sys.run(&["--device", "missing", "set", "5"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(stderr(&output), "No backlight devices matched --device missing\n\
+                                 hint: `backctl list` shows the devices available\n");
+    assert_eq!(sys.run(&["set", "5x%"]).status.code(), Some(2));
+
+    // The daemon still holds the device after it is unplugged
+    fs::remove_dir_all(sys.device_path("panel")).unwrap();
+    let output = sys.run(&["get"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).starts_with("The device went away while using "), "{}", stderr(&output));
+}
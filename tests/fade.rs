@@ -0,0 +1,32 @@
+mod common;
+
+use common::FakeSysfs;
+
+#[test]
+fn fade_lands_on_target() {
+    for easing in &["linear", "ease-in-out", "exponential"] {
+        let sys = FakeSysfs::new()
+            .backlight("acpi_video0", 10, 15)
+            .backlight("intel_backlight", 4000, 4882);
+        sys.ok(&["set", "30%", "--fade", "50ms", "--easing", easing]);
+        assert_eq!(sys.brightness("acpi_video0"), 4);
+        assert_eq!(sys.brightness("intel_backlight"), 1464);
+    }
+}
+
+#[test]
+fn fade_relative() {
+    let sys = FakeSysfs::new().backlight("panel", 0, 255);
+    sys.ok(&["inc", "100", "--fade", "20ms", "--fade-rate", "200"]);
+    assert_eq!(sys.brightness("panel"), 100);
+    sys.ok(&["dec", "300", "--fade", "0.02s", "--easing", "exponential", "--allow-off"]);
+    assert_eq!(sys.brightness("panel"), 0);
+}
+
+#[test]
+fn invalid_duration() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let output = sys.run(&["set", "50", "--fade", "soon"]);
+    assert!(!output.status.success());
+    assert_eq!(sys.brightness("panel"), 10);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::FakeSysfs;
+
+#[test]
+fn leds_are_a_separate_class() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 50, 100)
+        .led("tpacpi::kbd_backlight", 1, 2);
+
+    sys.ok(&["set", "100%"]);
+    assert_eq!(sys.brightness("panel"), 100);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 1);
+
+    sys.ok(&["--class", "leds", "set", "2"]);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 2);
+    assert_eq!(sys.brightness("panel"), 100);
+    assert_eq!(sys.ok(&["--class", "leds", "get"]), "2 100%\n");
+}
+
+#[test]
+fn device_matches_led_function() {
+    let sys = FakeSysfs::new()
+        .led("tpacpi::kbd_backlight", 0, 2)
+        .led("input3::capslock", 0, 1);
+
+    sys.ok(&["--class", "leds", "--device", "kbd_backlight", "inc", "1"]);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 1);
+    assert_eq!(sys.led_brightness("input3::capslock"), 0);
+
+    let output = sys.run(&["--class", "leds", "--device", "nothing", "get"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--class leds --device nothing"));
+}
+
+#[test]
+fn leds_dim_to_off() {
+    let sys = FakeSysfs::new().led("dell::kbd_backlight", 1, 2);
+    sys.ok(&["--class", "leds", "dec", "1"]);
+    assert_eq!(sys.led_brightness("dell::kbd_backlight"), 0);
+}
+
+#[test]
+fn daemon_serves_both_classes() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 50, 100)
+        .led("tpacpi::kbd_backlight", 0, 2);
+    let _daemon = sys.daemon(&["--no-hotplug"]);
+
+    sys.ok(&["--class", "leds", "set", "50%"]);
+    sys.ok(&["set", "20"]);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 1);
+    assert_eq!(sys.brightness("panel"), 20);
+}
@@ -0,0 +1,76 @@
+extern crate backctl;
+
+mod common;
+
+use backctl::{batch, select, Backlights, Class, Curve, CurvePolicy, ErrorKind, Selector, Source, Update};
+use common::FakeSysfs;
+
+fn enumerate(sys: &FakeSysfs, classes: &[Class]) -> Vec<backctl::Backlight> {
+    Backlights::new(&Source::Sysfs(sys.root().to_path_buf()), classes).unwrap().collect()
+}
+
+#[test]
+fn enumerates_and_applies_updates() {
+    let sys = FakeSysfs::new()
+        .backlight("intel_backlight", 100, 1000)
+        .led("tpacpi::kbd_backlight", 1, 2);
+
+    let backlights = enumerate(&sys, &[Class::Backlight]);
+    assert_eq!(backlights.len(), 1);
+    assert_eq!(backlights[0].sysname, "intel_backlight");
+    assert_eq!(backlights[0].get_max_brightness().unwrap(), 1000);
+
+    let update = Update::inc("10%").unwrap().with_curve(CurvePolicy::Fixed(Curve::Linear));
+    for backlight in backlights {
+        update.apply(backlight).unwrap();
+    }
+    assert_eq!(sys.brightness("intel_backlight"), 200);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 1);
+}
+
+#[test]
+fn selects_like_the_cli() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .connected_backlight("intel_backlight", "eDP-1", 100, 1000);
+
+    let primary = select::primary(enumerate(&sys, &[Class::Backlight]));
+    assert_eq!(primary.len(), 1);
+    assert_eq!(primary[0].sysname, "acpi_video0");
+
+    // The firmware device drives the same internal panel, so shares its output
+    let selector = Selector { outputs: vec!["eDP-1".to_string()], ..Selector::default() };
+    let chosen = selector.select(enumerate(&sys, &[Class::Backlight])).unwrap();
+    assert_eq!(chosen.len(), 2);
+    assert!(chosen.iter().all(|bl| bl.connector.as_ref().unwrap().name == "eDP-1"));
+
+    let selector = Selector { devices: vec!["nothing*".to_string()], ..Selector::default() };
+    let err = selector.select(enumerate(&sys, &[Class::Backlight])).err().unwrap();
+    match *err.kind() {
+        ErrorKind::NoMatchingDevices(_) => {}
+        ref other => panic!("unexpected error {:?}", other),
+    }
+    assert_eq!(backctl::exit_code(&err), 3);
+}
+
+#[test]
+fn rejects_bad_values() {
+    let err = Update::set("lots").err().unwrap();
+    assert_eq!(backctl::exit_code(&err), 2);
+}
+
+#[test]
+fn rolls_back_strict_batches() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .backlight("intel_backlight", 100, 1000)
+        .link_brightness("acpi_video0", "/proc/self/stat");
+
+    let update = Update::set("50%").unwrap().with_curve(CurvePolicy::Fixed(Curve::Linear));
+    let updates = enumerate(&sys, &[Class::Backlight]).into_iter().map(|bl| (bl, update.clone())).collect();
+    let outcome = batch::apply(updates, None, true);
+    assert!(outcome.written.is_empty());
+    assert_eq!(backctl::exit_code(&outcome.result.err().unwrap()), 6);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::bus::{Call, FakeBus};
+use common::FakeSysfs;
+
+#[test]
+fn logind_backend() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let bus = FakeBus::start(&sys.bus_path(), sys.root(), None);
+
+    sys.ok(&["--backend", "logind", "set", "40"]);
+    assert_eq!(bus.calls(), vec![Call { subsystem: "backlight".into(), name: "panel".into(), value: 40 }]);
+    assert_eq!(sys.brightness("panel"), 40);
+
+    // Relative updates still read the current value from sysfs
+    sys.ok(&["--backend", "logind", "inc", "5"]);
+    assert_eq!(bus.calls()[0].value, 45);
+}
+
+#[test]
+fn logind_refused() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let _bus = FakeBus::start(&sys.bus_path(), sys.root(), Some("org.freedesktop.DBus.Error.AccessDenied"));
+
+    let output = sys.run(&["--backend", "logind", "set", "40"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("org.freedesktop.DBus.Error.AccessDenied"), "{}", stderr);
+    assert_eq!(sys.brightness("panel"), 10);
+}
+
+#[test]
+fn sysfs_backend_skips_logind() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let bus = FakeBus::start(&sys.bus_path(), sys.root(), None);
+
+    sys.ok(&["--backend", "sysfs", "set", "40"]);
+    sys.ok(&["set", "50"]);
+    assert!(bus.calls().is_empty());
+    assert_eq!(sys.brightness("panel"), 50);
+}
+
+#[test]
+fn falls_back_when_not_writable() {
+    // Permission bits don't stop root, but the kernel refuses everyone
+    // writes to read-only /proc/sys entries with EACCES
+    let sys = FakeSysfs::new()
+        .backlight("panel", 10, 100)
+        .link_brightness("panel", "/proc/sys/kernel/osrelease");
+    let bus = FakeBus::start(&sys.bus_path(), sys.root(), None);
+
+    sys.ok(&["set", "40"]);
+    assert_eq!(bus.calls(), vec![Call { subsystem: "backlight".into(), name: "panel".into(), value: 40 }]);
+}
+
+#[test]
+fn hotplugged_devices_keep_backend() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let bus = FakeBus::start(&sys.bus_path(), sys.root(), None);
+    let _daemon = sys.daemon(&["--backend", "logind"]);
+    let sys = sys.backlight("late", 10, 100);
+    let start = Instant::now();
+    while sys.run(&["get", "--raw"]).stdout != b"panel 10\nlate 10\n" {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    sys.ok(&["set", "30"]);
+    let mut names: Vec<String> = bus.calls().into_iter().map(|call| call.name).collect();
+    names.sort();
+    assert_eq!(names, vec!["late", "panel"]);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::FakeSysfs;
+
+fn syspath(sys: &FakeSysfs, name: &str) -> String {
+    sys.device_path(name).canonicalize().unwrap().to_string_lossy().into_owned()
+}
+
+#[test]
+fn get_json() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .connected_backlight("ddcci5", "DP-2", 20, 100);
+    assert_eq!(sys.ok(&["--format", "json", "get"]), format!(
+        "[{{\"device\":\"acpi_video0\",\"syspath\":\"{}\",\"type\":\"firmware\",\"output\":null,\
+         \"brightness\":5,\"max\":10,\"percent\":50}},\
+         {{\"device\":\"ddcci5\",\"syspath\":\"{}\",\"type\":\"raw\",\"output\":\"DP-2\",\
+         \"brightness\":20,\"max\":100,\"percent\":20}}]\n",
+        syspath(&sys, "acpi_video0"), syspath(&sys, "ddcci5")));
+}
+
+#[test]
+fn set_reports_old_and_new() {
+    let sys = FakeSysfs::new().backlight("panel", 300, 1200);
+    // Nothing is printed unless asked for
+    assert_eq!(sys.ok(&["set", "50%"]), "");
+    let out = sys.ok(&["--format", "json", "inc", "10%"]);
+    assert!(out.contains("\"old\":600,\"brightness\":720,\"max\":1200,\"percent\":60}"), "{}", out);
+}
+
+#[test]
+fn env_for_eval() {
+    let sys = FakeSysfs::new().led("tpacpi::kbd_backlight", 1, 2);
+    let out = sys.ok(&["--class", "leds", "--format", "env", "set", "2"]);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines, [
+        format!("BACKCTL_TPACPI__KBD_BACKLIGHT_SYSPATH={}", syspath_led(&sys)).as_str(),
+        "BACKCTL_TPACPI__KBD_BACKLIGHT_TYPE=''",
+        "BACKCTL_TPACPI__KBD_BACKLIGHT_OUTPUT=''",
+        "BACKCTL_TPACPI__KBD_BACKLIGHT_OLD_BRIGHTNESS=1",
+        "BACKCTL_TPACPI__KBD_BACKLIGHT_BRIGHTNESS=2",
+        "BACKCTL_TPACPI__KBD_BACKLIGHT_MAX_BRIGHTNESS=2",
+        "BACKCTL_TPACPI__KBD_BACKLIGHT_PERCENT=100",
+    ]);
+}
+
+fn syspath_led(sys: &FakeSysfs) -> String {
+    sys.led_path("tpacpi::kbd_backlight").canonicalize().unwrap().to_string_lossy().into_owned()
+}
+
+#[test]
+fn list_json_includes_actual() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 30, 100)
+        .attribute("panel", "actual_brightness", "25");
+    let out = sys.ok(&["--format", "json", "list"]);
+    assert!(out.contains("\"brightness\":30,\"actual\":25,"), "{}", out);
+}
+
+#[test]
+fn off_reports_in_env() {
+    let sys = FakeSysfs::new().backlight("panel", 30, 100);
+    let state = sys.root().join("state");
+    let out = sys.ok(&["--format", "env", "off", "--state-dir", state.to_str().unwrap()]);
+    assert!(out.contains("BACKCTL_PANEL_OLD_BRIGHTNESS=30\nBACKCTL_PANEL_BRIGHTNESS=0\n"), "{}", out);
+}
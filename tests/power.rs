@@ -0,0 +1,127 @@
+mod common;
+
+use std::fs;
+
+use common::FakeSysfs;
+
+fn power(sys: &FakeSysfs, name: &str) -> String {
+    fs::read_to_string(sys.device_path(name).join("bl_power")).unwrap().trim().to_string()
+}
+
+fn state_dir(sys: &FakeSysfs) -> String {
+    sys.root().join("state").to_string_lossy().into_owned()
+}
+
+#[test]
+fn off_and_on_through_bl_power() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 30, 100)
+        .attribute("panel", "bl_power", "0");
+    let state = state_dir(&sys);
+
+    sys.ok(&["off", "--state-dir", &state]);
+    assert_eq!(power(&sys, "panel"), "4");
+    assert_eq!(sys.brightness("panel"), 30);
+
+    sys.ok(&["on", "--state-dir", &state]);
+    assert_eq!(power(&sys, "panel"), "0");
+    assert_eq!(sys.brightness("panel"), 30);
+}
+
+#[test]
+fn on_restores_brightness_reset_while_off() {
+    let sys = FakeSysfs::new()
+        .backlight("panel", 30, 100)
+        .attribute("panel", "bl_power", "0");
+    let state = state_dir(&sys);
+
+    sys.ok(&["off", "--state-dir", &state]);
+    // Some drivers zero the brightness while powered down
+    sys.ok(&["set", "0"]);
+    sys.ok(&["on", "--state-dir", &state]);
+    assert_eq!(sys.brightness("panel"), 30);
+}
+
+#[test]
+fn falls_back_to_zero_brightness() {
+    let sys = FakeSysfs::new().backlight("panel", 30, 100);
+    let state = state_dir(&sys);
+
+    sys.ok(&["off", "--state-dir", &state]);
+    assert_eq!(sys.brightness("panel"), 0);
+    // A second off mustn't forget the level by remembering zero
+    sys.ok(&["off", "--state-dir", &state]);
+    sys.ok(&["on", "--state-dir", &state]);
+    assert_eq!(sys.brightness("panel"), 30);
+}
+
+#[test]
+fn toggle() {
+    let sys = FakeSysfs::new()
+        .backlight("external", 30, 100)
+        .backlight("panel", 70, 100)
+        .attribute("panel", "bl_power", "0");
+    let state = state_dir(&sys);
+
+    sys.ok(&["toggle", "--state-dir", &state]);
+    assert_eq!(power(&sys, "panel"), "4");
+    assert_eq!(sys.brightness("external"), 0);
+
+    sys.ok(&["toggle", "--state-dir", &state]);
+    assert_eq!(power(&sys, "panel"), "0");
+    assert_eq!(sys.brightness("panel"), 70);
+    assert_eq!(sys.brightness("external"), 30);
+}
+
+#[test]
+fn failures_dont_stop_other_devices() {
+    let sys = FakeSysfs::new()
+        .backlight("a", 30, 100)
+        .backlight("b", 30, 100)
+        .attribute("b", "bl_power", "0");
+    // A bl_power that can't be read or written
+    fs::create_dir(sys.device_path("a").join("bl_power")).unwrap();
+    let state = state_dir(&sys);
+
+    let output = sys.run(&["--all", "off", "--state-dir", &state]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Failed to update 1 of 2 devices; a: "), "{}", stderr);
+    assert!(stderr.contains("; updated b"), "{}", stderr);
+    assert_eq!(power(&sys, "b"), "4");
+
+    // --strict turns b back on
+    let output = sys.run(&["--all", "toggle", "--strict", "--state-dir", &state]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("; rolled back b"));
+    assert_eq!(power(&sys, "b"), "4");
+    sys.ok(&["--device", "b", "on", "--state-dir", &state]);
+    let output = sys.run(&["--all", "off", "--strict", "--state-dir", &state]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("; rolled back b"));
+    assert_eq!(power(&sys, "b"), "0");
+    assert_eq!(sys.brightness("b"), 30);
+}
+
+#[test]
+fn strict_leaves_devices_as_they_were() {
+    let sys = FakeSysfs::new()
+        .backlight("a", 30, 100)
+        .backlight("b", 30, 100)
+        .attribute("b", "bl_power", "4")
+        .backlight("c", 30, 100)
+        .attribute("c", "bl_power", "0");
+    fs::create_dir(sys.device_path("a").join("bl_power")).unwrap();
+    let state = state_dir(&sys);
+
+    // b was already off, so putting things back mustn't turn it on
+    let output = sys.run(&["--all", "off", "--strict", "--state-dir", &state]);
+    assert!(!output.status.success());
+    assert_eq!(power(&sys, "b"), "4");
+    assert_eq!(power(&sys, "c"), "0");
+
+    // Nor may a failed on turn off c, which was already lit
+    let output = sys.run(&["--all", "on", "--strict", "--state-dir", &state]);
+    assert!(!output.status.success());
+    assert_eq!(power(&sys, "b"), "4");
+    assert_eq!(power(&sys, "c"), "0");
+    assert_eq!(sys.brightness("c"), 30);
+}
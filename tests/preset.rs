@@ -0,0 +1,101 @@
+mod common;
+
+use std::fs;
+
+use common::FakeSysfs;
+
+const PRESETS: &str = "[devices.intel_backlight]\n\
+                       alias = \"screen\"\n\
+                       \n\
+                       [presets.movie]\n\
+                       screen = \"20%\"\n\
+                       kbd_backlight = \"0\"\n\
+                       \n\
+                       [presets.reading]\n\
+                       screen = \"800\"\n\
+                       kbd_backlight = \"1\"\n";
+
+fn laptop() -> FakeSysfs {
+    FakeSysfs::new()
+        .backlight("intel_backlight", 500, 1000)
+        .led("tpacpi::kbd_backlight", 2, 2)
+        .config(PRESETS)
+}
+
+#[test]
+fn applies_to_screen_and_keyboard() {
+    let sys = laptop();
+    sys.ok(&["preset", "movie"]);
+    assert_eq!(sys.brightness("intel_backlight"), 200);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 0);
+
+    sys.ok(&["preset", "reading", "--fade", "20ms"]);
+    assert_eq!(sys.brightness("intel_backlight"), 800);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 1);
+
+    // The usual selection flags narrow a preset down
+    sys.ok(&["--class", "leds", "preset", "movie"]);
+    assert_eq!(sys.brightness("intel_backlight"), 800);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 0);
+}
+
+#[test]
+fn unknown_preset() {
+    let sys = laptop();
+    let output = sys.run(&["preset", "party"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(sys.brightness("intel_backlight"), 500);
+}
+
+#[test]
+fn lists_presets() {
+    let sys = laptop();
+    assert_eq!(sys.ok(&["preset", "list"]),
+               "movie kbd_backlight=0 screen=20%\n\
+                reading kbd_backlight=1 screen=800\n");
+    assert_eq!(sys.ok(&["--format", "json", "preset", "list"]),
+               "[{\"name\":\"movie\",\"levels\":{\"kbd_backlight\":\"0\",\"screen\":\"20%\"}},\
+                {\"name\":\"reading\",\"levels\":{\"kbd_backlight\":\"1\",\"screen\":\"800\"}}]\n");
+    assert_eq!(sys.ok(&["--format", "env", "preset", "list"]),
+               "BACKCTL_PRESET_MOVIE_KBD_BACKLIGHT=0\n\
+                BACKCTL_PRESET_MOVIE_SCREEN=20%\n\
+                BACKCTL_PRESET_READING_KBD_BACKLIGHT=1\n\
+                BACKCTL_PRESET_READING_SCREEN=800\n");
+}
+
+#[test]
+fn reserved_names() {
+    let sys = laptop();
+    let output = sys.run(&["preset", "save", "list"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!fs::read_to_string(sys.config_path()).unwrap().contains("[presets.list]"));
+
+    let sys = sys.config(&format!("{}[presets.save]\nscreen = \"1\"\n", PRESETS));
+    let output = sys.run(&["config", "check"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("presets.\"save\" can't be applied"));
+    assert_eq!(sys.run(&["preset", "list"]).status.code(), Some(2));
+}
+
+#[test]
+fn saves_current_state() {
+    let sys = laptop();
+    fs::write(sys.config_path(), format!("# my presets\n{}", PRESETS)).unwrap();
+    sys.ok(&["set", "30%"]);
+    sys.ok(&["preset", "save", "movie"]);
+    sys.ok(&["preset", "save", "dusk"]);
+
+    let config = fs::read_to_string(sys.config_path()).unwrap();
+    assert!(config.starts_with("# my presets\n"), "{}", config);
+    assert_eq!(config.matches("[presets.movie]").count(), 1, "{}", config);
+    assert!(config.contains("[presets.reading]"));
+    assert!(config.ends_with("[presets.dusk]\n\
+                              screen = \"300\"\n\
+                              \"tpacpi::kbd_backlight\" = \"2\"\n"), "{}", config);
+
+    sys.ok(&["preset", "reading"]);
+    sys.ok(&["preset", "movie"]);
+    assert_eq!(sys.brightness("intel_backlight"), 300);
+    assert_eq!(sys.led_brightness("tpacpi::kbd_backlight"), 2);
+    assert!(sys.ok(&["config", "check"]).ends_with(": ok\n"));
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::FakeSysfs;
+
+#[test]
+fn firmware_wins_over_raw() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .connected_backlight("intel_backlight", "eDP-1", 100, 1000);
+
+    sys.ok(&["set", "100%"]);
+    assert_eq!(sys.brightness("acpi_video0"), 10);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+    assert_eq!(sys.ok(&["get", "--raw"]), "10\n");
+
+    // Naming the raw device still reaches it
+    sys.ok(&["--device", "intel_backlight", "set", "500"]);
+    assert_eq!(sys.brightness("intel_backlight"), 500);
+}
+
+#[test]
+fn firmware_wins_over_gpu_backlight() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .gpu_backlight("amdgpu_bl0", 100, 255);
+
+    sys.ok(&["set", "50"]);
+    assert_eq!(sys.brightness("acpi_video0"), 10);
+    assert_eq!(sys.brightness("amdgpu_bl0"), 100);
+
+    // Alone, the GPU's backlight is still used
+    let sys = FakeSysfs::new().gpu_backlight("amdgpu_bl0", 100, 255);
+    sys.ok(&["set", "50"]);
+    assert_eq!(sys.brightness("amdgpu_bl0"), 50);
+}
+
+#[test]
+fn save_and_restore_every_device() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .connected_backlight("intel_backlight", "eDP-1", 100, 1000);
+    let state = sys.root().join("state");
+    let state = state.to_str().unwrap();
+
+    sys.ok(&["save", "--state-dir", state]);
+    sys.ok(&["--all", "set", "1"]);
+    sys.ok(&["restore", "--state-dir", state]);
+    assert_eq!(sys.brightness("acpi_video0"), 5);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+}
+
+#[test]
+fn platform_wins_over_raw() {
+    let sys = FakeSysfs::new()
+        .connected_backlight("intel_backlight", "eDP-1", 100, 1000)
+        .backlight("thinkpad_screen", 3, 15)
+        .attribute("thinkpad_screen", "type", "platform");
+
+    sys.ok(&["set", "0"]);
+    assert_eq!(sys.brightness("thinkpad_screen"), 0);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+}
+
+#[test]
+fn one_per_connector() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .connected_backlight("intel_backlight", "eDP-1", 100, 1000)
+        .connected_backlight("ddcci5", "DP-2", 20, 100);
+
+    sys.ok(&["set", "50%"]);
+    assert_eq!(sys.brightness("acpi_video0"), 5);
+    assert_eq!(sys.brightness("ddcci5"), 50);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+}
+
+#[test]
+fn all_broadcasts() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .connected_backlight("intel_backlight", "eDP-1", 100, 1000);
+
+    sys.ok(&["--all", "set", "100%"]);
+    assert_eq!(sys.brightness("acpi_video0"), 10);
+    assert_eq!(sys.brightness("intel_backlight"), 1000);
+    // list always shows everything
+    assert_eq!(sys.ok(&["list"]).lines().count(), 3);
+}
+
+#[test]
+fn select_by_output() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware")
+        .connected_backlight("intel_backlight", "eDP-1", 100, 1000)
+        .connected_backlight("ddcci5", "DP-2", 20, 100);
+
+    sys.ok(&["--output", "DP-2", "set", "80"]);
+    assert_eq!(sys.brightness("ddcci5"), 80);
+    assert_eq!(sys.brightness("acpi_video0"), 5);
+
+    // The firmware interface drives the built-in panel, so it answers for
+    // eDP-1 and, being preferred, is the one written
+    sys.ok(&["--output", "card0-eDP-1", "set", "100%"]);
+    assert_eq!(sys.brightness("acpi_video0"), 10);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+    assert_eq!(sys.brightness("ddcci5"), 80);
+
+    let output = sys.run(&["--output", "HDMI-A-1", "get"]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("No backlight devices matched --output HDMI-A-1\n"));
+}
+
+#[test]
+fn list_shows_output() {
+    let sys = FakeSysfs::new()
+        .connected_backlight("intel_backlight", "eDP-1", 100, 1000)
+        .backlight("panel", 1, 10);
+    let out = sys.ok(&["list"]);
+    let rows: Vec<Vec<&str>> = out.lines().map(|l| l.split_whitespace().collect()).collect();
+    assert_eq!(rows[0][6], "OUTPUT");
+    assert_eq!(rows[1][..1], ["intel_backlight"]);
+    assert_eq!(rows[1][6], "eDP-1");
+    assert_eq!(rows[2][6], "-");
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::FakeSysfs;
+
+#[test]
+fn get_single_device() {
+    let sys = FakeSysfs::new().backlight("panel", 300, 1200);
+    assert_eq!(sys.ok(&["get"]), "300 25%\n");
+    assert_eq!(sys.ok(&["get", "--raw"]), "300\n");
+    assert_eq!(sys.ok(&["get", "--percent"]), "25%\n");
+}
+
+#[test]
+fn get_names_multiple_devices() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .backlight("intel_backlight", 1000, 1000);
+    assert_eq!(sys.ok(&["get", "--percent"]), "acpi_video0 50%\nintel_backlight 100%\n");
+}
+
+#[test]
+fn list_devices() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .attribute("acpi_video0", "type", "firmware");
+    let out = sys.ok(&["list"]);
+    let row: Vec<&str> = out.lines().nth(1).unwrap().split_whitespace().collect();
+    assert_eq!(&row[..5], &["acpi_video0", "firmware", "5", "5", "10"]);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::fs;
+
+use common::FakeSysfs;
+
+fn state_dir(sys: &FakeSysfs) -> String {
+    sys.root().join("state").to_string_lossy().into_owned()
+}
+
+#[test]
+fn save_and_restore() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 7, 10)
+        .backlight("intel_backlight", 300, 1000)
+        .attribute("intel_backlight", "uevent", "ID_PATH=pci-0000:00:02.0");
+    let state = state_dir(&sys);
+    sys.ok(&["save", "--state-dir", &state]);
+    assert!(sys.root().join("state/backlight:acpi_video0").is_file());
+    assert!(sys.root().join("state/pci-0000:00:02.0:backlight:intel_backlight").is_file());
+
+    sys.ok(&["set", "100%"]);
+    sys.ok(&["restore", "--state-dir", &state]);
+    assert_eq!(sys.brightness("acpi_video0"), 7);
+    assert_eq!(sys.brightness("intel_backlight"), 300);
+}
+
+#[test]
+fn restore_selected_device() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 7, 10)
+        .backlight("intel_backlight", 300, 1000);
+    let state = state_dir(&sys);
+    sys.ok(&["save", "--state-dir", &state]);
+    sys.ok(&["set", "0"]);
+    sys.ok(&["--device", "intel_backlight", "restore", "--state-dir", &state]);
+    assert_eq!(sys.brightness("acpi_video0"), 0);
+    assert_eq!(sys.brightness("intel_backlight"), 300);
+}
+
+#[test]
+fn never_restores_zero() {
+    let sys = FakeSysfs::new().backlight("panel", 0, 1000);
+    let state = state_dir(&sys);
+    sys.ok(&["save", "--state-dir", &state]);
+    sys.ok(&["restore", "--state-dir", &state]);
+    assert_eq!(sys.brightness("panel"), 1);
+    sys.ok(&["set", "0"]);
+    sys.ok(&["restore", "--state-dir", &state, "--floor", "5%"]);
+    assert_eq!(sys.brightness("panel"), 50);
+}
+
+#[test]
+fn rescales_when_max_changes() {
+    let sys = FakeSysfs::new().backlight("panel", 50, 100);
+    let state = state_dir(&sys);
+    sys.ok(&["save", "--state-dir", &state]);
+    fs::write(sys.device_path("panel").join("max_brightness"), "1000\n").unwrap();
+    sys.ok(&["restore", "--state-dir", &state]);
+    assert_eq!(sys.brightness("panel"), 500);
+}
+
+#[test]
+fn missing_state_is_skipped() {
+    let sys = FakeSysfs::new().backlight("panel", 50, 100);
+    let state = state_dir(&sys);
+    let output = sys.run(&["restore", "--state-dir", &state]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No saved brightness for panel"));
+    assert_eq!(sys.brightness("panel"), 50);
+}
+
+#[test]
+fn restore_carries_on_past_failures() {
+    let sys = FakeSysfs::new().backlight("a", 40, 100).backlight("b", 60, 100);
+    let state = state_dir(&sys);
+    sys.ok(&["--all", "save", "--state-dir", &state]);
+    sys.ok(&["--all", "set", "10"]);
+    // A brightness that can't be written
+    let brightness = sys.device_path("a").join("brightness");
+    fs::remove_file(&brightness).unwrap();
+    fs::create_dir(&brightness).unwrap();
+
+    let output = sys.run(&["--all", "restore", "--state-dir", &state]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Failed to update 1 of 2 devices; a: "), "{}", stderr);
+    assert!(stderr.contains("; updated b"), "{}", stderr);
+    assert_eq!(sys.brightness("b"), 60);
+
+    sys.ok(&["--device", "b", "set", "10"]);
+    let output = sys.run(&["--all", "restore", "--strict", "--state-dir", &state]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("; rolled back b"));
+    assert_eq!(sys.brightness("b"), 10);
+}
+
+#[test]
+fn restore_doesnt_count_devices_without_state() {
+    let sys = FakeSysfs::new().backlight("a", 40, 100).backlight("b", 60, 100);
+    let state = state_dir(&sys);
+    sys.ok(&["--device", "a", "save", "--state-dir", &state]);
+    let brightness = sys.device_path("a").join("brightness");
+    fs::remove_file(&brightness).unwrap();
+    fs::create_dir(&brightness).unwrap();
+
+    let output = sys.run(&["--all", "restore", "--state-dir", &state]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("No saved brightness for b"), "{}", stderr);
+    assert!(!stderr.contains("updated b"), "{}", stderr);
+}
@@ -0,0 +1,232 @@
+mod common;
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::FakeSysfs;
+
+#[test]
+fn set_raw() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    sys.ok(&["set", "42"]);
+    assert_eq!(sys.brightness("panel"), 42);
+}
+
+#[test]
+fn set_percent() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 1200);
+    sys.ok(&["set", "25%"]);
+    assert_eq!(sys.brightness("panel"), 300);
+}
+
+#[test]
+fn inc_and_dec_raw() {
+    let sys = FakeSysfs::new().backlight("panel", 50, 100);
+    sys.ok(&["inc", "7"]);
+    assert_eq!(sys.brightness("panel"), 57);
+    sys.ok(&["dec", "20"]);
+    assert_eq!(sys.brightness("panel"), 37);
+}
+
+#[test]
+fn inc_and_dec_percent() {
+    let sys = FakeSysfs::new().backlight("panel", 400, 800);
+    sys.ok(&["inc", "10%"]);
+    assert_eq!(sys.brightness("panel"), 480);
+    sys.ok(&["dec", "25%"]);
+    assert_eq!(sys.brightness("panel"), 280);
+}
+
+#[test]
+fn clamps_to_max() {
+    let sys = FakeSysfs::new().backlight("panel", 90, 100);
+    sys.ok(&["inc", "50"]);
+    assert_eq!(sys.brightness("panel"), 100);
+    sys.ok(&["set", "150%"]);
+    assert_eq!(sys.brightness("panel"), 100);
+}
+
+#[test]
+fn huge_values() {
+    let sys = FakeSysfs::new().backlight("panel", 90, 1000);
+    sys.ok(&["set", "99999999%"]);
+    assert_eq!(sys.brightness("panel"), 1000);
+    sys.ok(&["dec", "3000000%", "--allow-off"]);
+    assert_eq!(sys.brightness("panel"), 0);
+    sys.ok(&["inc", "3000000%"]);
+    assert_eq!(sys.brightness("panel"), 1000);
+
+    let output = sys.run(&["dec", "--", "-2147483648"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "Brightness '-2147483648' is out of range\n");
+    assert_eq!(sys.run(&["set", "99999999999"]).status.code(), Some(2));
+    assert_eq!(sys.brightness("panel"), 1000);
+}
+
+#[test]
+fn clamps_to_zero() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    sys.ok(&["dec", "50", "--allow-off"]);
+    assert_eq!(sys.brightness("panel"), 0);
+}
+
+#[test]
+fn dec_stops_at_floor() {
+    let sys = FakeSysfs::new().backlight("panel", 50, 100);
+    sys.ok(&["dec", "100%"]);
+    assert_eq!(sys.brightness("panel"), 1);
+    sys.ok(&["set", "50"]);
+    sys.ok(&["dec", "100%", "--floor", "10%"]);
+    assert_eq!(sys.brightness("panel"), 10);
+}
+
+#[test]
+fn per_device_floor() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .backlight("intel_backlight", 500, 1000);
+    sys.ok(&["dec", "100%", "--floor", "20", "--floor", "acpi*=2"]);
+    assert_eq!(sys.brightness("acpi_video0"), 2);
+    assert_eq!(sys.brightness("intel_backlight"), 20);
+}
+
+#[test]
+fn floor_never_brightens() {
+    let sys = FakeSysfs::new().backlight("panel", 3, 100);
+    sys.ok(&["dec", "1", "--floor", "10"]);
+    assert_eq!(sys.brightness("panel"), 3);
+}
+
+#[test]
+fn set_ignores_floor() {
+    let sys = FakeSysfs::new().backlight("panel", 50, 100);
+    sys.ok(&["set", "0"]);
+    assert_eq!(sys.brightness("panel"), 0);
+}
+
+#[test]
+fn updates_every_device() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .backlight("intel_backlight", 100, 1000);
+    sys.ok(&["set", "50%"]);
+    assert_eq!(sys.brightness("acpi_video0"), 5);
+    assert_eq!(sys.brightness("intel_backlight"), 500);
+}
+
+#[test]
+fn device_filter() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .backlight("intel_backlight", 100, 1000);
+    sys.ok(&["set", "--device", "intel_*", "0"]);
+    assert_eq!(sys.brightness("acpi_video0"), 5);
+    assert_eq!(sys.brightness("intel_backlight"), 0);
+}
+
+#[test]
+fn property_filter() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .backlight("intel_backlight", 100, 1000)
+        .attribute("acpi_video0", "uevent", "ID_PATH=acpi-LNXVIDEO:00");
+    sys.ok(&["inc", "--match", "ID_PATH=acpi-*", "1"]);
+    assert_eq!(sys.brightness("acpi_video0"), 6);
+    assert_eq!(sys.brightness("intel_backlight"), 100);
+}
+
+#[test]
+fn no_matching_device() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let output = sys.run(&["set", "--device", "missing", "0"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No backlight devices matched --device missing"));
+    assert_eq!(sys.brightness("panel"), 10);
+}
+
+#[test]
+fn verify_waits_for_hardware() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let brightness = sys.device_path("panel").join("brightness");
+    let actual = sys.device_path("panel").join("actual_brightness");
+    // Stand in for firmware that takes a moment to follow writes
+    let firmware = thread::spawn(move || {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            let value = fs::read_to_string(&brightness).unwrap();
+            if value.trim() == "40" {
+                thread::sleep(Duration::from_millis(50));
+                fs::write(&actual, value).unwrap();
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    });
+
+    sys.ok(&["set", "40", "--verify", "--verify-timeout", "2s"]);
+    firmware.join().unwrap();
+    assert_eq!(sys.ok(&["get", "--actual", "--raw"]), "40\n");
+}
+
+#[test]
+fn verify_reports_ignored_writes() {
+    let sys = FakeSysfs::new().backlight("panel", 10, 100);
+    let output = sys.run(&["set", "40", "--verify", "--verify-timeout", "20ms", "--verify-retries", "1"]);
+    assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(7));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("panel reports brightness 10 after 40 was written\n"));
+    // Without --verify nothing checks
+    sys.ok(&["set", "50"]);
+    assert_eq!(sys.ok(&["get", "--actual", "--raw"]), "10\n");
+}
+
+#[test]
+fn verify_skips_devices_without_actual_brightness() {
+    let sys = FakeSysfs::new().led("input3::capslock", 0, 1);
+    sys.ok(&["--class", "leds", "set", "1", "--verify"]);
+    assert_eq!(sys.led_brightness("input3::capslock"), 1);
+}
+
+/// Makes `name`'s brightness impossible to write
+fn break_device(sys: &FakeSysfs, name: &str) {
+    let brightness = sys.device_path(name).join("brightness");
+    fs::remove_file(&brightness).unwrap();
+    fs::create_dir(&brightness).unwrap();
+}
+
+#[test]
+fn failures_dont_stop_other_devices() {
+    let sys = FakeSysfs::new()
+        .backlight("a", 10, 100)
+        .backlight("b", 10, 100)
+        .backlight("c", 10, 100);
+    break_device(&sys, "b");
+
+    let output = sys.run(&["set", "50"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Failed to update 1 of 3 devices; b: "), "{}", stderr);
+    assert!(stderr.ends_with("; updated a, c\n"), "{}", stderr);
+    assert_eq!(sys.brightness("a"), 50);
+    assert_eq!(sys.brightness("c"), 50);
+
+    sys.run(&["set", "70", "--fade", "20ms"]);
+    assert_eq!(sys.brightness("a"), 70);
+    assert_eq!(sys.brightness("c"), 70);
+}
+
+#[test]
+fn strict_rolls_back() {
+    let sys = FakeSysfs::new()
+        .backlight("a", 10, 100)
+        .backlight("b", 10, 100)
+        .backlight("c", 20, 100);
+    break_device(&sys, "b");
+
+    let output = sys.run(&["set", "50", "--strict"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).ends_with("; rolled back a, c\n"));
+    assert_eq!(sys.brightness("a"), 10);
+    assert_eq!(sys.brightness("c"), 20);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Stdio};
+
+use common::FakeSysfs;
+
+/// Kills the watcher even when an assertion fails
+struct Watcher(Child);
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn streams_changes() {
+    let sys = FakeSysfs::new()
+        .backlight("acpi_video0", 5, 10)
+        .backlight("intel_backlight", 100, 1000);
+    let mut watcher = Watcher(sys.command(&["watch", "--interval", "20ms"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap());
+    let mut lines = BufReader::new(watcher.0.stdout.take().unwrap()).lines();
+    let mut next = || lines.next().unwrap().unwrap();
+
+    assert_eq!(next(), "acpi_video0 5 50%");
+    assert_eq!(next(), "intel_backlight 100 10%");
+
+    // As if a firmware hotkey changed it
+    let sys = sys.attribute("intel_backlight", "actual_brightness", "250");
+    assert_eq!(next(), "intel_backlight 250 25%");
+    let _sys = sys.attribute("acpi_video0", "actual_brightness", "10");
+    assert_eq!(next(), "acpi_video0 10 100%");
+}
+
+#[test]
+fn streams_json() {
+    let sys = FakeSysfs::new().backlight("panel", 3, 12);
+    let mut watcher = Watcher(sys.command(&["watch", "--json", "--interval", "20ms"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap());
+    let mut lines = BufReader::new(watcher.0.stdout.take().unwrap()).lines();
+    let root = sys.device_path("panel").canonicalize().unwrap();
+    assert_eq!(lines.next().unwrap().unwrap(),
+               format!(r#"{{"device":"panel","syspath":"{}","type":"raw","output":null,"brightness":3,"max":12,"percent":25}}"#,
+                       root.display()));
+}
+
+#[test]
+fn streams_env() {
+    let sys = FakeSysfs::new().backlight("panel", 3, 12);
+    let mut watcher = Watcher(sys.command(&["--format", "env", "watch", "--interval", "20ms"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap());
+    let lines: Vec<String> = BufReader::new(watcher.0.stdout.take().unwrap()).lines()
+        .take(6)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(lines[3..], ["BACKCTL_PANEL_BRIGHTNESS=3", "BACKCTL_PANEL_MAX_BRIGHTNESS=12",
+                            "BACKCTL_PANEL_PERCENT=25"]);
+}